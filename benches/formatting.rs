@@ -0,0 +1,74 @@
+// Formatting and filtering over synthetic devices, so these run on any host
+// without touching SetupAPI.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use print_guid::format::{csv_row, table};
+use print_guid::{Filter, Guid, WinDev};
+use regex::Regex;
+
+const DEVICES: usize = 10_000;
+
+fn devices() -> Vec<WinDev> {
+    (0..DEVICES)
+        .map(|i| {
+            let dev = WinDev::builder()
+                .desc(format!("Generic device {}", i))
+                .guid(Guid(
+                    0x4d36e972_e325_11ce_bfc1_08002be10318 + (i % 16) as u128,
+                ))
+                .class_name(format!("Class{}", i % 16))
+                .instance_id(format!("PCI\\VEN_8086&DEV_{:04X}\\{}", i, i))
+                .hardware_ids([
+                    format!("PCI\\VEN_8086&DEV_{:04X}&REV_01", i),
+                    format!("PCI\\VEN_8086&DEV_{:04X}", i),
+                ])
+                .manufacturer("Intel");
+            // leave some unnamed, as on a real machine
+            match i % 5 {
+                0 => dev.build(),
+                _ => dev.fname(format!("Device {}", i)).build(),
+            }
+        })
+        .collect()
+}
+
+fn formatting(c: &mut Criterion) {
+    let devs = devices();
+    c.bench_function("text", |b| {
+        b.iter(|| {
+            for dev in &devs {
+                black_box(dev.format_text());
+            }
+        })
+    });
+    c.bench_function("json", |b| {
+        b.iter(|| black_box(serde_json::to_string(&devs).unwrap()))
+    });
+    c.bench_function("csv", |b| {
+        b.iter(|| {
+            for dev in &devs {
+                black_box(csv_row(dev));
+            }
+        })
+    });
+    c.bench_function("table", |b| {
+        b.iter(|| black_box(table(&devs, "Unknown", Some(40))))
+    });
+}
+
+fn filtering(c: &mut Criterion) {
+    let devs = devices();
+    let filter = Filter {
+        exclude_guids: vec![Guid(0x4d36e972_e325_11ce_bfc1_08002be10318)],
+        name_contains: Some("device 9".to_string()),
+        desc_regex: Some(Regex::new(r"device \d+5$").unwrap()),
+        hwid_contains: Some("rev_01".to_string()),
+        ..Filter::default()
+    };
+    c.bench_function("filter", |b| {
+        b.iter(|| devs.iter().filter(|dev| filter.matches(dev)).count())
+    });
+}
+
+criterion_group!(benches, formatting, filtering);
+criterion_main!(benches);
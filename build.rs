@@ -0,0 +1,34 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Bakes the resolved `windows` crate version and the target triple into the
+// binary for `--build-info`.
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let lock = Path::new(&manifest_dir).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock.display());
+
+    let windows_version = fs::read_to_string(&lock)
+        .ok()
+        .and_then(|lock| locked_version(&lock, "windows"))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=BUILD_WINDOWS_VERSION={}", windows_version);
+    println!(
+        "cargo:rustc-env=BUILD_TARGET={}",
+        env::var("TARGET").unwrap()
+    );
+}
+
+// Finds `version = "..."` right after `name = "<package>"` in Cargo.lock.
+fn locked_version(lock: &str, package: &str) -> Option<String> {
+    let name_line = format!("name = \"{}\"", package);
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line == name_line {
+            let version = lines.next()?.strip_prefix("version = \"")?;
+            return Some(version.trim_end_matches('"').to_string());
+        }
+    }
+    None
+}
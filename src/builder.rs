@@ -0,0 +1,168 @@
+use crate::{DeviceInterface, Guid, WinDev};
+
+/// Builds a `WinDev` without SetupAPI, for tests and synthetic data. Fields
+/// that aren't set keep their empty defaults.
+#[derive(Default)]
+pub struct WinDevBuilder {
+    dev: WinDev,
+}
+
+impl WinDevBuilder {
+    pub fn fname(mut self, fname: impl Into<String>) -> Self {
+        self.dev.fname = Some(fname.into());
+        self
+    }
+
+    pub fn desc(mut self, desc: impl Into<String>) -> Self {
+        self.dev.desc = Some(desc.into());
+        self
+    }
+
+    pub fn guid(mut self, guid: Guid) -> Self {
+        self.dev.guid = guid;
+        self
+    }
+
+    pub fn class_name(mut self, class_name: impl Into<String>) -> Self {
+        self.dev.class_name = Some(class_name.into());
+        self
+    }
+
+    pub fn class_string(mut self, class_string: impl Into<String>) -> Self {
+        self.dev.class_string = Some(class_string.into());
+        self
+    }
+
+    pub fn hardware_ids<I, S>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.dev.hardware_ids = ids.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn compatible_ids<I, S>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.dev.compatible_ids = ids.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn manufacturer(mut self, manufacturer: impl Into<String>) -> Self {
+        self.dev.manufacturer = Some(manufacturer.into());
+        self
+    }
+
+    pub fn instance_id(mut self, instance_id: impl Into<String>) -> Self {
+        self.dev.instance_id = instance_id.into();
+        self
+    }
+
+    pub fn service(mut self, service: impl Into<String>) -> Self {
+        self.dev.service = Some(service.into());
+        self
+    }
+
+    pub fn enumerator(mut self, enumerator: impl Into<String>) -> Self {
+        self.dev.enumerator = Some(enumerator.into());
+        self
+    }
+
+    pub fn bus_type(mut self, bus_type: Guid) -> Self {
+        self.dev.bus_type = Some(bus_type);
+        self
+    }
+
+    pub fn location(mut self, location: impl Into<String>) -> Self {
+        self.dev.location = Some(location.into());
+        self
+    }
+
+    pub fn driver_key(mut self, driver_key: impl Into<String>) -> Self {
+        self.dev.driver_key = Some(driver_key.into());
+        self
+    }
+
+    pub fn config_flags(mut self, config_flags: u32) -> Self {
+        self.dev.config_flags = Some(config_flags);
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: u32) -> Self {
+        self.dev.capabilities = Some(capabilities);
+        self
+    }
+
+    pub fn interfaces(mut self, interfaces: Vec<DeviceInterface>) -> Self {
+        self.dev.interfaces = interfaces;
+        self
+    }
+
+    pub fn dev_inst(mut self, dev_inst: u32) -> Self {
+        self.dev.dev_inst = dev_inst;
+        self
+    }
+
+    pub fn diagnostics(mut self, diagnostics: Vec<String>) -> Self {
+        self.dev.diagnostics = diagnostics;
+        self
+    }
+
+    pub fn build(self) -> WinDev {
+        self.dev
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::DEFAULT_SEPARATOR;
+
+    fn disk() -> WinDev {
+        WinDev::builder()
+            .fname("Samsung SSD 980")
+            .desc("Disk drive")
+            .guid(Guid(0x4d36e967_e325_11ce_bfc1_08002be10318))
+            .class_name("DiskDrive")
+            .instance_id("SCSI\\DISK&VEN_NVME\\5&1")
+            .hardware_ids(["SCSI\\DiskNVMe", "GenDisk"])
+            .build()
+    }
+
+    #[test]
+    fn built_devices_display_as_a_text_block() {
+        let text = disk().to_string();
+        assert!(text.starts_with(&format!("{}\n", DEFAULT_SEPARATOR)));
+        assert!(text.ends_with(DEFAULT_SEPARATOR));
+        for line in [
+            "Dev Name: Samsung SSD 980\n",
+            "Dev Desc: Disk drive\n",
+            "Instance ID: SCSI\\DISK&VEN_NVME\\5&1\n",
+            "Class: DiskDrive\n",
+            "GUID (canonical): {4d36e967-e325-11ce-bfc1-08002be10318}\n",
+            "Service: None\n",
+            "Hardware ID: SCSI\\DiskNVMe\nHardware ID: GenDisk\n",
+        ] {
+            assert!(text.contains(line), "missing {:?} in\n{}", line, text);
+        }
+    }
+
+    #[test]
+    fn built_devices_serialize_to_json() {
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&disk()).unwrap()).unwrap();
+        assert_eq!(json["friendly_name"], "Samsung SSD 980");
+        assert_eq!(json["guid_hex"], "0x4d36e967e32511cebfc108002be10318");
+        assert_eq!(json["class_name"], "DiskDrive");
+        assert_eq!(
+            json["hardware_ids"],
+            serde_json::json!(["SCSI\\DiskNVMe", "GenDisk"])
+        );
+        assert_eq!(json["bus_type"], serde_json::Value::Null);
+        // the devnode handle is process-local and left out
+        assert!(json.get("dev_inst").is_none());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::{Guid, WinDev};
+use regex::Regex;
+
+/// Every criterion a device has to meet to be listed. All set criteria must
+/// match, except that `exclude_guids` overrides everything else.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Class GUIDs that are never listed.
+    pub exclude_guids: Vec<Guid>,
+    /// Only list this class GUID.
+    pub guid: Option<Guid>,
+    /// Only list these class GUIDs, e.g. those of a class name; empty for any.
+    pub class_guids: Vec<Guid>,
+    /// Only list class GUIDs from this allowlist. Unlike `class_guids`, an
+    /// empty list lets nothing through.
+    pub allowed_guids: Option<Vec<Guid>>,
+    /// Lowest class GUID listed, compared as `u128`.
+    pub min_guid: Option<Guid>,
+    /// Highest class GUID listed, compared as `u128`.
+    pub max_guid: Option<Guid>,
+    /// Case-insensitive substring of the friendly name.
+    pub name_contains: Option<String>,
+    pub name_regex: Option<Regex>,
+    pub desc_regex: Option<Regex>,
+    /// Only list disabled (`Some(true)`) or enabled (`Some(false)`) devices,
+    /// see `WinDev::disabled`.
+    pub disabled: Option<bool>,
+    /// Case-insensitive substring of any hardware ID, e.g. a VID/PID pair.
+    pub hwid_contains: Option<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, dev: &WinDev) -> bool {
+        let text_matches = |field: &Option<String>, re: &Regex| {
+            field.as_deref().is_some_and(|text| re.is_match(text))
+        };
+
+        !self.exclude_guids.contains(&dev.guid)
+            && self.guid.is_none_or(|guid| guid == dev.guid)
+            && (self.class_guids.is_empty() || self.class_guids.contains(&dev.guid))
+            && self
+                .allowed_guids
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(&dev.guid))
+            && self.min_guid.is_none_or(|min| dev.guid >= min)
+            && self.max_guid.is_none_or(|max| dev.guid <= max)
+            && self.name_contains.as_ref().is_none_or(|needle| {
+                dev.fname
+                    .as_ref()
+                    .is_some_and(|name| name.to_lowercase().contains(&needle.to_lowercase()))
+            })
+            && self
+                .name_regex
+                .as_ref()
+                .is_none_or(|re| text_matches(&dev.fname, re))
+            && self
+                .desc_regex
+                .as_ref()
+                .is_none_or(|re| text_matches(&dev.desc, re))
+            && self
+                .disabled
+                .is_none_or(|disabled| dev.disabled() == disabled)
+            && self.hwid_contains.as_ref().is_none_or(|needle| {
+                let needle = needle.to_lowercase();
+                dev.hardware_ids
+                    .iter()
+                    .any(|id| id.to_lowercase().contains(&needle))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISK_DRIVE: Guid = Guid(0x4d36e967_e325_11ce_bfc1_08002be10318);
+    const NET: Guid = Guid(0x4d36e972_e325_11ce_bfc1_08002be10318);
+
+    fn named(guid: Guid, name: &str) -> WinDev {
+        WinDev::builder().guid(guid).fname(name).build()
+    }
+
+    #[test]
+    fn name_contains_ignores_case() {
+        let filter = Filter {
+            name_contains: Some("realtek".to_string()),
+            ..Filter::default()
+        };
+        assert!(filter.matches(&named(NET, "Realtek PCIe GbE Family Controller")));
+        assert!(filter.matches(&named(NET, "REALTEK USB Audio")));
+        assert!(!filter.matches(&named(NET, "Intel(R) Ethernet")));
+    }
+
+    #[test]
+    fn name_contains_skips_unnamed_devices() {
+        let filter = Filter {
+            name_contains: Some("realtek".to_string()),
+            ..Filter::default()
+        };
+        let unnamed = WinDev::builder().guid(NET).desc("Realtek").build();
+        assert!(!filter.matches(&unnamed));
+        // without the criterion unnamed devices are listed as usual
+        assert!(Filter::default().matches(&unnamed));
+    }
+
+    #[test]
+    fn name_contains_ands_with_the_class() {
+        let filter = Filter {
+            guid: Some(NET),
+            name_contains: Some("realtek".to_string()),
+            ..Filter::default()
+        };
+        assert!(filter.matches(&named(NET, "Realtek PCIe GbE")));
+        assert!(!filter.matches(&named(DISK_DRIVE, "Realtek Card Reader")));
+    }
+
+    #[test]
+    fn excludes_every_listed_class() {
+        let usb = Guid(0x36fc9e60_c465_11cf_8056_444553540000);
+        let filter = Filter {
+            exclude_guids: vec![DISK_DRIVE, NET],
+            ..Filter::default()
+        };
+        assert!(!filter.matches(&named(DISK_DRIVE, "WD Disk")));
+        assert!(!filter.matches(&named(NET, "Ethernet")));
+        assert!(filter.matches(&named(usb, "Root Hub")));
+    }
+
+    #[test]
+    fn exclude_wins_over_the_included_class() {
+        let filter = Filter {
+            exclude_guids: vec![NET],
+            guid: Some(NET),
+            ..Filter::default()
+        };
+        assert!(!filter.matches(&named(NET, "Ethernet")));
+    }
+
+    #[test]
+    fn every_set_criterion_must_match() {
+        let filter = Filter {
+            class_guids: vec![DISK_DRIVE, NET],
+            min_guid: Some(NET),
+            name_regex: Some(Regex::new("^Intel").unwrap()),
+            desc_regex: Some(Regex::new("Ethernet").unwrap()),
+            disabled: Some(false),
+            ..Filter::default()
+        };
+        let dev = |guid, name: &str, desc: &str, flags| {
+            WinDev::builder()
+                .guid(guid)
+                .fname(name)
+                .desc(desc)
+                .config_flags(flags)
+                .build()
+        };
+        assert!(filter.matches(&dev(NET, "Intel I219", "Intel Ethernet", 0)));
+        // below min_guid
+        assert!(!filter.matches(&dev(DISK_DRIVE, "Intel SSD", "Ethernet", 0)));
+        // fails the name pattern
+        assert!(!filter.matches(&dev(NET, "Realtek", "Realtek Ethernet", 0)));
+        // fails the description pattern
+        assert!(!filter.matches(&dev(NET, "Intel Wi-Fi", "Intel Wireless", 0)));
+        // disabled
+        assert!(!filter.matches(&dev(NET, "Intel I219", "Intel Ethernet", 0x1)));
+    }
+
+    #[test]
+    fn an_empty_allowlist_lets_nothing_through() {
+        let dev = named(NET, "Ethernet");
+        let filter = |allowed: Vec<Guid>| Filter {
+            allowed_guids: Some(allowed),
+            ..Filter::default()
+        };
+        assert!(!filter(Vec::new()).matches(&dev));
+        assert!(filter(vec![DISK_DRIVE, NET]).matches(&dev));
+        // while an empty class list means any class
+        assert!(Filter::default().matches(&dev));
+    }
+
+    #[test]
+    fn hwid_contains_checks_every_hardware_id() {
+        let filter = Filter {
+            hwid_contains: Some("vid_046d&pid_c52b".to_string()),
+            ..Filter::default()
+        };
+        let receiver = WinDev::builder()
+            .hardware_ids(["USB\\VID_046D&PID_C52B&REV_1211", "USB\\VID_046D&PID_C52B"])
+            .build();
+        let hub = WinDev::builder()
+            .hardware_ids(["USB\\ROOT_HUB30&VID8086&PID7AE0", "USB\\ROOT_HUB30"])
+            .build();
+        assert!(filter.matches(&receiver));
+        assert!(!filter.matches(&hub));
+        assert!(!filter.matches(&WinDev::builder().build()));
+    }
+}
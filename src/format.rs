@@ -0,0 +1,598 @@
+use crate::{DeviceProperty, PropertySelection, WinDev};
+use std::fmt::Display;
+
+const LABEL_COLOR: &str = "\x1b[1;36m";
+const GUID_COLOR: &str = "\x1b[33m";
+const CLASS_COLOR: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+/// The line the text block puts above and below each device by default.
+pub const DEFAULT_SEPARATOR: &str = "---------------------------";
+
+/// Which GUID representations the text block shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GuidFormat {
+    Decimal,
+    Hex,
+    Canonical,
+    All,
+}
+
+/// Options for the human readable text block.
+pub struct TextFormat<'a> {
+    /// Shown in place of a missing friendly name.
+    pub placeholder: &'a str,
+    /// Highlight labels, GUIDs and class names with ANSI colors.
+    pub color: bool,
+    /// Show the description as the name when there is no friendly name, like
+    /// Device Manager does.
+    pub name_fallback: bool,
+    pub guid_format: GuidFormat,
+    /// Which property lines to include; instance ID, class and GUID are
+    /// always shown.
+    pub properties: PropertySelection,
+    /// The line above and below each device; empty for none.
+    pub separator: &'a str,
+}
+
+impl Default for TextFormat<'_> {
+    fn default() -> Self {
+        Self {
+            placeholder: "Unknown",
+            color: false,
+            name_fallback: true,
+            guid_format: GuidFormat::All,
+            properties: PropertySelection::all(),
+            separator: DEFAULT_SEPARATOR,
+        }
+    }
+}
+
+impl TextFormat<'_> {
+    /// Wraps `text` in the given ANSI color when coloring is enabled.
+    pub fn paint(&self, color: &str, text: &str) -> String {
+        match self.color {
+            true => format!("{}{}{}", color, text, RESET),
+            false => text.to_string(),
+        }
+    }
+
+    fn label(&self, text: &str) -> String {
+        self.paint(LABEL_COLOR, text)
+    }
+
+    fn guid(&self, text: &str) -> String {
+        self.paint(GUID_COLOR, text)
+    }
+
+    fn line(&self, f: &mut dyn std::fmt::Write, label: &str, value: &str) -> std::fmt::Result {
+        writeln!(f, "{} {}", self.label(label), value)
+    }
+
+    /// The name shown for a device, falling back to the description and then
+    /// the placeholder.
+    pub fn name<'b>(&'b self, dev: &'b WinDev) -> &'b str {
+        let fallback = dev.desc.as_deref().filter(|_| self.name_fallback);
+        dev.fname
+            .as_deref()
+            .or(fallback)
+            .unwrap_or(self.placeholder)
+    }
+
+    /// Paints a class name the same way the text block does.
+    pub fn class(&self, text: &str) -> String {
+        self.paint(CLASS_COLOR, text)
+    }
+}
+
+/// A device paired with the options used to display it, see `WinDev::display`.
+pub struct Formatted<'a> {
+    pub(crate) dev: &'a WinDev,
+    pub(crate) format: &'a TextFormat<'a>,
+}
+
+impl Display for Formatted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let separator = self.format.separator;
+        if separator.is_empty() {
+            // without a closing separator the last line's break would be
+            // left dangling
+            let mut lines = String::new();
+            self.lines(&mut lines)?;
+            return f.write_str(lines.trim_end_matches('\n'));
+        }
+        writeln!(f, "{}", separator)?;
+        self.lines(f)?;
+        f.write_str(separator)
+    }
+}
+
+impl Formatted<'_> {
+    // The property lines between the separators, each ending in a newline.
+    fn lines(&self, f: &mut dyn std::fmt::Write) -> std::fmt::Result {
+        let (dev, fmt) = (self.dev, self.format);
+        let wanted = |p| fmt.properties.contains(p);
+        let class_name = dev.class_name.as_deref().unwrap_or("Unknown class");
+        if wanted(DeviceProperty::FriendlyName) {
+            fmt.line(f, "Dev Name:", fmt.name(dev))?;
+        }
+        if wanted(DeviceProperty::Description) {
+            fmt.line(f, "Dev Desc:", dev.desc.as_deref().unwrap_or("None"))?;
+        }
+        if wanted(DeviceProperty::Manufacturer) {
+            fmt.line(f, "Dev Mfg:", dev.manufacturer.as_deref().unwrap_or("None"))?;
+        }
+        fmt.line(f, "Instance ID:", &dev.instance_id)?;
+        if wanted(DeviceProperty::Enumerator) {
+            fmt.line(
+                f,
+                "Enumerator:",
+                dev.enumerator.as_deref().unwrap_or("None"),
+            )?;
+        }
+        if wanted(DeviceProperty::BusType) {
+            let bus_type = dev.bus_type.map(|guid| guid.to_string());
+            fmt.line(f, "Bus Type:", bus_type.as_deref().unwrap_or("None"))?;
+        }
+        fmt.line(f, "Class:", &fmt.class(class_name))?;
+        if wanted(DeviceProperty::ClassString) {
+            fmt.line(
+                f,
+                "Class (registry):",
+                dev.class_string.as_deref().unwrap_or("None"),
+            )?;
+        }
+        let guid_format = fmt.guid_format;
+        if matches!(guid_format, GuidFormat::Decimal | GuidFormat::All) {
+            fmt.line(f, "GUID:", &fmt.guid(&dev.guid.0.to_string()))?;
+        }
+        if matches!(guid_format, GuidFormat::Hex | GuidFormat::All) {
+            fmt.line(f, "GUID (hex):", &fmt.guid(&dev.guid.hex()))?;
+        }
+        if matches!(guid_format, GuidFormat::Canonical | GuidFormat::All) {
+            fmt.line(f, "GUID (canonical):", &fmt.guid(&dev.guid.to_string()))?;
+        }
+        if wanted(DeviceProperty::Service) {
+            fmt.line(f, "Service:", dev.service.as_deref().unwrap_or("None"))?;
+        }
+        if wanted(DeviceProperty::Location) {
+            fmt.line(f, "Location:", dev.location.as_deref().unwrap_or("None"))?;
+        }
+        if wanted(DeviceProperty::DriverKey) {
+            fmt.line(
+                f,
+                "Driver Key:",
+                dev.driver_key.as_deref().unwrap_or("None"),
+            )?;
+        }
+        if wanted(DeviceProperty::ConfigFlags) {
+            match dev.config_flags {
+                Some(flags) => {
+                    fmt.line(f, "Config Flags:", &format!("{:#010x}", flags))?;
+                    let disabled = if dev.disabled() { "yes" } else { "no" };
+                    fmt.line(f, "Disabled:", disabled)?;
+                }
+                None => fmt.line(f, "Config Flags:", "None")?,
+            }
+        }
+        if wanted(DeviceProperty::Capabilities) {
+            let capabilities = dev
+                .capabilities
+                .map(|bits| format!("{:#010x} ({})", bits, dev.capability_names().join(", ")));
+            fmt.line(
+                f,
+                "Capabilities:",
+                capabilities.as_deref().unwrap_or("None"),
+            )?;
+        }
+        if wanted(DeviceProperty::HardwareIds) {
+            for id in &dev.hardware_ids {
+                fmt.line(f, "Hardware ID:", id)?;
+            }
+        }
+        if wanted(DeviceProperty::CompatibleIds) {
+            for id in &dev.compatible_ids {
+                fmt.line(f, "Compatible ID:", id)?;
+            }
+        }
+        for interface in &dev.interfaces {
+            fmt.line(f, "Interface:", &interface.path)?;
+        }
+        for diagnostic in &dev.diagnostics {
+            fmt.line(f, "Read error:", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+pub const CSV_HEADER: &str = "friendly_name,description,guid_hex,class_name";
+
+// Quotes a field when it contains a delimiter, quote or line break,
+// doubling any embedded quotes.
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Renders one device as a CSV row matching `CSV_HEADER`.
+pub fn csv_row(dev: &WinDev) -> String {
+    [
+        csv_escape(dev.fname.as_deref().unwrap_or("")),
+        csv_escape(dev.desc.as_deref().unwrap_or("")),
+        dev.guid.to_string(),
+        csv_escape(dev.class_name.as_deref().unwrap_or("")),
+    ]
+    .join(",")
+}
+
+const TABLE_COLUMNS: [&str; 4] = ["Name", "Description", "Class", "GUID"];
+
+// Shortens a cell to `max` characters, marking the cut with an ellipsis.
+fn truncate(text: &str, max: Option<usize>) -> String {
+    match max {
+        Some(max) if text.chars().count() > max => {
+            let mut cut: String = text.chars().take(max.saturating_sub(1)).collect();
+            cut.push('…');
+            cut
+        }
+        _ => text.to_string(),
+    }
+}
+
+/// Lays the devices out as aligned Name, Description, Class and GUID columns,
+/// with a header row, sizing each column to its widest cell. Cells longer
+/// than `max_width` characters are truncated.
+pub fn table(devs: &[WinDev], placeholder: &str, max_width: Option<usize>) -> String {
+    let rows: Vec<[String; 4]> = devs
+        .iter()
+        .map(|dev| {
+            [
+                dev.fname.as_deref().unwrap_or(placeholder),
+                dev.desc.as_deref().unwrap_or(""),
+                dev.class_name.as_deref().unwrap_or(""),
+                &dev.guid.to_string(),
+            ]
+            .map(|cell| truncate(cell, max_width))
+        })
+        .collect();
+
+    let mut widths = TABLE_COLUMNS.map(|title| title.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let render = |cells: [&str; 4]| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+    let mut lines = vec![
+        render(TABLE_COLUMNS),
+        render(widths.map(|w| "-".repeat(w)).each_ref().map(String::as_str)),
+    ];
+    lines.extend(
+        rows.iter()
+            .map(|row| render(row.each_ref().map(String::as_str))),
+    );
+    lines.join("\n")
+}
+
+pub const XML_HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<devices>";
+pub const XML_FOOTER: &str = "</devices>";
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Missing values become self-closing elements.
+fn xml_element(name: &str, value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("<{0}>{1}</{0}>", name, xml_escape(value)),
+        None => format!("<{}/>", name),
+    }
+}
+
+/// Renders one `<device>` element, to be placed between `XML_HEADER` and
+/// `XML_FOOTER`.
+pub fn xml_device(dev: &WinDev) -> String {
+    format!(
+        "  <device>\n    {}\n    {}\n    {}\n    {}\n  </device>",
+        xml_element("friendlyName", dev.fname.as_deref()),
+        xml_element("description", dev.desc.as_deref()),
+        xml_element("guid", Some(&dev.guid.to_string())),
+        xml_element("className", dev.class_name.as_deref()),
+    )
+}
+
+// logfmt value: bare unless it holds a space, quote, equals sign or control
+// character, in which case it is quoted with quotes and backslashes escaped.
+fn logfmt_value(value: &str) -> String {
+    if !value.contains(|c: char| c == ' ' || c == '"' || c == '=' || c.is_control()) {
+        return value.to_string();
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Renders one device as a single logfmt line (`name=... desc=... class=...
+/// guid=... instance=...`) for log collectors; missing values are left empty.
+pub fn logfmt_record(dev: &WinDev) -> String {
+    [
+        ("name", dev.fname.as_deref().unwrap_or("")),
+        ("desc", dev.desc.as_deref().unwrap_or("")),
+        ("class", dev.class_name.as_deref().unwrap_or("")),
+        ("guid", &dev.guid.to_string()),
+        ("instance", &dev.instance_id),
+    ]
+    .iter()
+    .map(|(key, value)| format!("{}={}", key, logfmt_value(value)))
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+pub const DOT_HEADER: &str = "digraph devices {\n  node [shape=box];";
+pub const DOT_FOOTER: &str = "}";
+
+// Double-quoted DOT string. Backslashes are escaped too, since label strings
+// give \n, \l and friends a meaning of their own.
+fn dot_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => {}
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Renders one device as a DOT node statement labeled with its name and
+/// class, identified by its devnode so edges can refer to it.
+pub fn dot_node(dev: &WinDev, name: &str) -> String {
+    let class_name = dev.class_name.as_deref().unwrap_or("Unknown class");
+    format!(
+        "  dev{} [label={}];",
+        dev.dev_inst,
+        dot_string(&format!("{}\n[{}]", name, class_name))
+    )
+}
+
+/// Renders the DOT edge statement from one devnode to another.
+pub fn dot_edge(parent: u32, child: u32) -> String {
+    format!("  dev{} -> dev{};", parent, child)
+}
+
+// Statements inside @( ) each add their output to the array, so devices can
+// be written one per line without separators.
+pub const POWERSHELL_HEADER: &str = "@(";
+pub const POWERSHELL_FOOTER: &str = ")";
+
+// Single-quoted PowerShell literal. PowerShell also ends these strings on
+// typographic single quotes, so those are doubled like the ASCII one.
+fn powershell_string(value: Option<&str>) -> String {
+    let Some(value) = value else {
+        return "$null".to_string();
+    };
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('\'');
+    for c in value.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Renders one device as a `[PSCustomObject]@{ ... }` literal, to be placed
+/// between `POWERSHELL_HEADER` and `POWERSHELL_FOOTER`.
+pub fn powershell_object(dev: &WinDev) -> String {
+    let hardware_ids: Vec<String> = dev
+        .hardware_ids
+        .iter()
+        .map(|id| powershell_string(Some(id)))
+        .collect();
+    format!(
+        "  [PSCustomObject]@{{ FriendlyName = {}; Description = {}; Guid = {}; ClassName = {}; InstanceId = {}; HardwareIds = @({}) }}",
+        powershell_string(dev.fname.as_deref()),
+        powershell_string(dev.desc.as_deref()),
+        powershell_string(Some(&dev.guid.to_string())),
+        powershell_string(dev.class_name.as_deref()),
+        powershell_string(Some(&dev.instance_id)),
+        hardware_ids.join(", "),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Guid;
+
+    fn only_name() -> TextFormat<'static> {
+        TextFormat {
+            properties: "name".parse().unwrap(),
+            ..TextFormat::default()
+        }
+    }
+
+    #[test]
+    fn missing_names_show_the_placeholder() {
+        let dev = WinDev::builder().build();
+        let text = dev.display(&only_name()).to_string();
+        assert!(text.contains("Dev Name: Unknown\n"));
+        assert!(!text.contains("Unkown"));
+
+        let format = TextFormat {
+            placeholder: "",
+            ..only_name()
+        };
+        let text = dev.display(&format).to_string();
+        assert!(text.contains("Dev Name: \n"));
+    }
+
+    #[test]
+    fn guid_format_picks_the_lines() {
+        let dev = WinDev::builder()
+            .guid(Guid(0x4d36e967_e325_11ce_bfc1_08002be10318))
+            .build();
+        let decimal = "GUID: 102635673738035704292817236064645219096\n";
+        let hex = "GUID (hex): 0x4d36e967e32511cebfc108002be10318\n";
+        let canonical = "GUID (canonical): {4d36e967-e325-11ce-bfc1-08002be10318}\n";
+        let cases = [
+            (GuidFormat::Decimal, [true, false, false]),
+            (GuidFormat::Hex, [false, true, false]),
+            (GuidFormat::Canonical, [false, false, true]),
+            (GuidFormat::All, [true, true, true]),
+        ];
+        for (guid_format, shown) in cases {
+            let format = TextFormat {
+                guid_format,
+                ..TextFormat::default()
+            };
+            let text = dev.display(&format).to_string();
+            for (line, shown) in [decimal, hex, canonical].iter().zip(shown) {
+                assert_eq!(text.contains(line), shown, "{:?}: {}", guid_format, line);
+            }
+        }
+    }
+
+    #[test]
+    fn csv_quotes_delimiters_quotes_and_line_breaks() {
+        assert_eq!(csv_escape("plain"), "plain");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_escape("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn csv_rows_leave_missing_fields_empty() {
+        let dev = WinDev::builder()
+            .desc("Ports (COM & LPT), legacy")
+            .guid(Guid(0x4d36e978_e325_11ce_bfc1_08002be10318))
+            .build();
+        assert_eq!(
+            csv_row(&dev),
+            ",\"Ports (COM & LPT), legacy\",{4d36e978-e325-11ce-bfc1-08002be10318},"
+        );
+    }
+
+    #[test]
+    fn xml_escapes_markup_and_closes_missing_elements() {
+        assert_eq!(
+            xml_escape("<a href=\"x\">Tom & Jerry's</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+        let dev = WinDev::builder().fname("R&D <lab>").build();
+        let xml = xml_device(&dev);
+        assert!(xml.contains("<friendlyName>R&amp;D &lt;lab&gt;</friendlyName>"));
+        assert!(xml.contains("<description/>"));
+        assert!(xml.contains("<className/>"));
+    }
+
+    #[test]
+    fn powershell_doubles_single_quotes() {
+        assert_eq!(powershell_string(Some("Realtek")), "'Realtek'");
+        assert_eq!(powershell_string(Some("Joe's hub")), "'Joe''s hub'");
+        // typographic quotes end the literal too
+        assert_eq!(
+            powershell_string(Some("Joe\u{2019}s")),
+            "'Joe\u{2019}\u{2019}s'"
+        );
+        assert_eq!(powershell_string(None), "$null");
+    }
+
+    #[test]
+    fn powershell_objects_list_every_field() {
+        let dev = WinDev::builder()
+            .fname("O'Brien's webcam")
+            .instance_id("USB\\VID_046D")
+            .hardware_ids(["USB\\VID_046D&PID_085E", "USB\\VID_046D"])
+            .build();
+        assert_eq!(
+            powershell_object(&dev),
+            "  [PSCustomObject]@{ FriendlyName = 'O''Brien''s webcam'; Description = $null; \
+             Guid = '{00000000-0000-0000-0000-000000000000}'; ClassName = $null; \
+             InstanceId = 'USB\\VID_046D'; \
+             HardwareIds = @('USB\\VID_046D&PID_085E', 'USB\\VID_046D') }"
+        );
+    }
+
+    #[test]
+    fn dot_escapes_label_strings() {
+        assert_eq!(dot_string("plain"), "\"plain\"");
+        assert_eq!(dot_string("a \"b\" \\c"), "\"a \\\"b\\\" \\\\c\"");
+        assert_eq!(dot_string("two\r\nlines"), "\"two\\nlines\"");
+    }
+
+    #[test]
+    fn dot_nodes_and_edges_use_the_devnode() {
+        let dev = WinDev::builder().class_name("USB").dev_inst(12).build();
+        assert_eq!(
+            dot_node(&dev, "Generic \"USB\" Hub"),
+            "  dev12 [label=\"Generic \\\"USB\\\" Hub\\n[USB]\"];"
+        );
+        assert_eq!(dot_edge(3, 12), "  dev3 -> dev12;");
+    }
+
+    #[test]
+    fn logfmt_quotes_only_when_needed() {
+        assert_eq!(logfmt_value("USB"), "USB");
+        assert_eq!(logfmt_value(""), "");
+        assert_eq!(logfmt_value("USB Root Hub"), "\"USB Root Hub\"");
+        assert_eq!(logfmt_value("a=b"), "\"a=b\"");
+        assert_eq!(logfmt_value("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(logfmt_value("C:\\x\ty"), "\"C:\\\\x\\ty\"");
+    }
+
+    #[test]
+    fn logfmt_records_keep_the_field_order() {
+        let dev = WinDev::builder()
+            .fname("USB Root Hub (USB 3.0)")
+            .class_name("USB")
+            .guid(Guid(0x36fc9e60_c465_11cf_8056_444553540000))
+            .instance_id("USB\\ROOT_HUB30\\4&1")
+            .build();
+        assert_eq!(
+            logfmt_record(&dev),
+            "name=\"USB Root Hub (USB 3.0)\" desc= class=USB \
+             guid={36fc9e60-c465-11cf-8056-444553540000} instance=USB\\ROOT_HUB30\\4&1"
+        );
+    }
+}
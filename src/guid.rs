@@ -0,0 +1,64 @@
+use crate::{format_guid, parse_guid};
+use serde::{Serialize, Serializer};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+/// A GUID in the `GUID::to_u128` layout, so ordering and comparisons work on
+/// the number while `Display` gives the canonical brace form.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Guid(pub u128);
+
+impl Guid {
+    /// The `0x`-prefixed hex form shown as "GUID (hex)".
+    pub fn hex(self) -> String {
+        format!("{:#x}", self.0)
+    }
+}
+
+impl Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_guid(self.0))
+    }
+}
+
+/// Serializes as the canonical brace form.
+impl Serialize for Guid {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+/// Accepts the brace/dashed form as well as bare hex, see `parse_guid`.
+impl FromStr for Guid {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_guid(s).map(Guid)
+    }
+}
+
+impl From<u128> for Guid {
+    fn from(value: u128) -> Self {
+        Guid(value)
+    }
+}
+
+impl From<Guid> for u128 {
+    fn from(guid: Guid) -> Self {
+        guid.0
+    }
+}
+
+#[cfg(windows)]
+impl From<windows::core::GUID> for Guid {
+    fn from(guid: windows::core::GUID) -> Self {
+        Guid(guid.to_u128())
+    }
+}
+
+#[cfg(windows)]
+impl From<Guid> for windows::core::GUID {
+    fn from(guid: Guid) -> Self {
+        windows::core::GUID::from_u128(guid.0)
+    }
+}
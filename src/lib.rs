@@ -0,0 +1,809 @@
+use anyhow::{anyhow, Result};
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+mod builder;
+mod filter;
+pub mod format;
+mod guid;
+#[cfg(any(windows, test))]
+mod reader;
+#[cfg(windows)]
+mod setupapi;
+pub mod snapshot;
+
+pub use builder::WinDevBuilder;
+pub use filter::Filter;
+pub use format::{Formatted, GuidFormat, TextFormat};
+pub use guid::Guid;
+#[cfg(windows)]
+pub use setupapi::{
+    child_dev_insts, class_guids_from_name, enumerate_devices, get_class_name, parent_dev_inst,
+    with_retries, DeviceEnumerator, DeviceInfoSet, DeviceList, DeviceListIter, ReadContext,
+};
+
+// From cfgmgr32.h, kept here so `WinDev::disabled` works off Windows too.
+const CONFIGFLAG_DISABLED: u32 = 0x1;
+
+// The CM_DEVCAP_* bits from cfgmgr32.h with the names they are shown by.
+const CAPABILITIES: [(u32, &str); 11] = [
+    (0x0001, "LockSupported"),
+    (0x0002, "EjectSupported"),
+    (0x0004, "Removable"),
+    (0x0008, "DockDevice"),
+    (0x0010, "UniqueId"),
+    (0x0020, "SilentInstall"),
+    (0x0040, "RawDeviceOk"),
+    (0x0080, "SurpriseRemovalOk"),
+    (0x0100, "HardwareDisabled"),
+    (0x0200, "NonDynamic"),
+    (0x0400, "SecureDevice"),
+];
+
+/// The SetupAPI registry properties this crate knows how to read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceProperty {
+    FriendlyName,
+    Description,
+    Manufacturer,
+    Service,
+    Location,
+    DriverKey,
+    HardwareIds,
+    ClassString,
+    Enumerator,
+    BusType,
+    CompatibleIds,
+    ConfigFlags,
+    Capabilities,
+}
+
+impl DeviceProperty {
+    const ALL: [DeviceProperty; 13] = [
+        DeviceProperty::FriendlyName,
+        DeviceProperty::Description,
+        DeviceProperty::Manufacturer,
+        DeviceProperty::Service,
+        DeviceProperty::Location,
+        DeviceProperty::DriverKey,
+        DeviceProperty::HardwareIds,
+        DeviceProperty::ClassString,
+        DeviceProperty::Enumerator,
+        DeviceProperty::BusType,
+        DeviceProperty::CompatibleIds,
+        DeviceProperty::ConfigFlags,
+        DeviceProperty::Capabilities,
+    ];
+
+    /// The short name accepted by `--properties`.
+    pub fn name(self) -> &'static str {
+        match self {
+            DeviceProperty::FriendlyName => "name",
+            DeviceProperty::Description => "desc",
+            DeviceProperty::Manufacturer => "mfg",
+            DeviceProperty::Service => "service",
+            DeviceProperty::Location => "location",
+            DeviceProperty::DriverKey => "driver",
+            DeviceProperty::HardwareIds => "hwids",
+            DeviceProperty::ClassString => "class",
+            DeviceProperty::Enumerator => "enumerator",
+            DeviceProperty::BusType => "bus",
+            DeviceProperty::CompatibleIds => "compatids",
+            DeviceProperty::ConfigFlags => "flags",
+            DeviceProperty::Capabilities => "caps",
+        }
+    }
+}
+
+impl FromStr for DeviceProperty {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        DeviceProperty::ALL
+            .into_iter()
+            .find(|p| p.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = DeviceProperty::ALL.iter().map(|p| p.name()).collect();
+                anyhow!(
+                    "Unknown property {}, expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// The set of properties to read from SetupAPI and show in the output.
+/// Properties left out are never queried and stay empty on the `WinDev`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PropertySelection(Vec<DeviceProperty>);
+
+impl PropertySelection {
+    /// Every property this crate knows about.
+    pub fn all() -> Self {
+        Self(DeviceProperty::ALL.to_vec())
+    }
+
+    pub fn contains(&self, property: DeviceProperty) -> bool {
+        self.0.contains(&property)
+    }
+
+    /// Adds a property, e.g. one a filter needs even though it isn't shown.
+    pub fn insert(&mut self, property: DeviceProperty) {
+        if !self.contains(property) {
+            self.0.push(property);
+        }
+    }
+}
+
+/// Parses a comma separated list such as `name,desc,hwids`.
+impl FromStr for PropertySelection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut selection = Self(Vec::new());
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            selection.insert(name.parse()?);
+        }
+        Ok(selection)
+    }
+}
+
+/// Anything that can produce a device list; the SetupAPI enumerator in
+/// production, or a plain `Vec<WinDev>` of synthetic devices.
+pub trait DeviceSource {
+    fn devices(&self) -> Result<Vec<WinDev>>;
+
+    /// Yields the devices one by one. The default reads the whole list
+    /// first; sources that can produce devices lazily should override it.
+    fn iter_devices(&self) -> Box<dyn Iterator<Item = Result<WinDev>> + '_> {
+        match self.devices() {
+            Ok(devs) => Box::new(devs.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+}
+
+impl DeviceSource for Vec<WinDev> {
+    fn devices(&self) -> Result<Vec<WinDev>> {
+        Ok(self.clone())
+    }
+}
+
+/// A single device returned by the SetupAPI enumeration.
+///
+/// Two devices compare equal when their class GUID, friendly name,
+/// description and instance ID match; the other properties are ignored.
+#[derive(Clone, Default)]
+pub struct WinDev {
+    pub fname: Option<String>,
+    pub desc: Option<String>,
+    pub guid: Guid,
+    pub class_name: Option<String>,
+    /// The `SPDRP_CLASS` registry value, which can disagree with `class_name`.
+    pub class_string: Option<String>,
+    pub hardware_ids: Vec<String>,
+    /// The looser IDs Windows falls back to when no driver matches a
+    /// hardware ID, most specific first.
+    pub compatible_ids: Vec<String>,
+    pub manufacturer: Option<String>,
+    pub instance_id: String,
+    pub service: Option<String>,
+    /// The bus enumerator the device sits on (PCI, USB, ACPI, ...).
+    pub enumerator: Option<String>,
+    /// The bus type GUID (`SPDRP_BUSTYPEGUID`), in the same layout as `guid`.
+    pub bus_type: Option<Guid>,
+    pub location: Option<String>,
+    pub driver_key: Option<String>,
+    /// The `SPDRP_CONFIGFLAGS` bits (`CONFIGFLAG_*`), see `disabled`.
+    pub config_flags: Option<u32>,
+    /// The `SPDRP_CAPABILITIES` bits (`CM_DEVCAP_*`), see `capability_names`.
+    pub capabilities: Option<u32>,
+    /// Only filled in when interfaces were requested from the enumerator.
+    pub interfaces: Vec<DeviceInterface>,
+    /// Config Manager handle (`SP_DEVINFO_DATA::DevInst`) for the CM_* APIs.
+    /// Only meaningful inside the process that enumerated the device, so it
+    /// is left out of serialized output.
+    pub dev_inst: u32,
+    /// Property reads that failed for this device, unless the enumerator was
+    /// set to fail fast.
+    pub diagnostics: Vec<String>,
+}
+
+/// A device interface a device exposes, with the path `CreateFile` opens.
+#[derive(Clone, Debug, Serialize)]
+pub struct DeviceInterface {
+    pub interface_guid: Guid,
+    pub path: String,
+}
+
+impl WinDev {
+    // The fields equality, hashing and ordering look at. Devices without a
+    // friendly name sort after the named ones of their class.
+    fn key(&self) -> (Guid, bool, &Option<String>, &Option<String>, &str) {
+        (
+            self.guid,
+            self.fname.is_none(),
+            &self.fname,
+            &self.desc,
+            &self.instance_id,
+        )
+    }
+}
+
+impl PartialEq for WinDev {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for WinDev {}
+
+impl Hash for WinDev {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// Devices are ordered by class GUID, then friendly name with unnamed
+/// devices last, then description and instance ID. Equality uses the same
+/// fields, so two devices compare equal exactly when they are `==`.
+impl Ord for WinDev {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl PartialOrd for WinDev {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A device serialized with only the fields of the selected properties, as
+/// `--format json` prints it. Fields of properties that weren't read are
+/// left out rather than shown as null or empty.
+pub struct Selected<'a> {
+    dev: &'a WinDev,
+    properties: &'a PropertySelection,
+    // for serde_json::Value, whose numbers stop at 64 bits
+    decimal_as_string: bool,
+}
+
+impl Serialize for Selected<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        let dev = self.dev;
+        let wanted = |property| self.properties.contains(property);
+        let mut map = s.serialize_map(None)?;
+        if wanted(DeviceProperty::FriendlyName) {
+            map.serialize_entry("friendly_name", &dev.fname)?;
+        }
+        if wanted(DeviceProperty::Description) {
+            map.serialize_entry("description", &dev.desc)?;
+        }
+        match self.decimal_as_string {
+            true => map.serialize_entry("guid_decimal", &dev.guid.0.to_string())?,
+            false => map.serialize_entry("guid_decimal", &dev.guid.0)?,
+        }
+        map.serialize_entry("guid_hex", &dev.guid.hex())?;
+        map.serialize_entry("class_name", &dev.class_name)?;
+        if wanted(DeviceProperty::ClassString) {
+            map.serialize_entry("class_string", &dev.class_string)?;
+        }
+        if wanted(DeviceProperty::HardwareIds) {
+            map.serialize_entry("hardware_ids", &dev.hardware_ids)?;
+        }
+        if wanted(DeviceProperty::CompatibleIds) {
+            map.serialize_entry("compatible_ids", &dev.compatible_ids)?;
+        }
+        if wanted(DeviceProperty::Manufacturer) {
+            map.serialize_entry("manufacturer", &dev.manufacturer)?;
+        }
+        map.serialize_entry("instance_id", &dev.instance_id)?;
+        if wanted(DeviceProperty::Service) {
+            map.serialize_entry("service", &dev.service)?;
+        }
+        if wanted(DeviceProperty::Enumerator) {
+            map.serialize_entry("enumerator", &dev.enumerator)?;
+        }
+        if wanted(DeviceProperty::BusType) {
+            map.serialize_entry("bus_type", &dev.bus_type)?;
+        }
+        if wanted(DeviceProperty::Location) {
+            map.serialize_entry("location", &dev.location)?;
+        }
+        if wanted(DeviceProperty::DriverKey) {
+            map.serialize_entry("driver_key", &dev.driver_key)?;
+        }
+        if wanted(DeviceProperty::ConfigFlags) {
+            map.serialize_entry("config_flags", &dev.config_flags)?;
+        }
+        if wanted(DeviceProperty::Capabilities) {
+            map.serialize_entry("capabilities", &dev.capabilities)?;
+        }
+        map.serialize_entry("interfaces", &dev.interfaces)?;
+        map.serialize_entry("diagnostics", &dev.diagnostics)?;
+        map.end()
+    }
+}
+
+/// Serializes every field, as if all properties had been selected.
+impl Serialize for WinDev {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        self.selected(&PropertySelection::all()).serialize(s)
+    }
+}
+
+/// Renders a GUID in the `{xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}` form Windows uses.
+///
+/// Expects the value produced by `GUID::to_u128`, which already puts `data1`,
+/// `data2` and `data3` in their native (big-end first) order ahead of the
+/// byte array `data4`, so the fields can be sliced straight out of it.
+pub fn format_guid(guid: u128) -> String {
+    format!(
+        "{{{:08x}-{:04x}-{:04x}-{:04x}-{:012x}}}",
+        (guid >> 96) as u32,
+        (guid >> 80) as u16,
+        (guid >> 64) as u16,
+        (guid >> 48) as u16,
+        guid & 0xffff_ffff_ffff
+    )
+}
+
+/// Parses a GUID given either in brace/dashed form or as a bare hex string
+/// (optionally `0x`-prefixed) into the same `u128` layout as `format_guid`.
+pub fn parse_guid(s: &str) -> Result<u128> {
+    let s = s.trim();
+    let inner = s
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .unwrap_or(s);
+
+    let hex: String = if inner.contains('-') {
+        let groups: Vec<&str> = inner.split('-').collect();
+        let lens: Vec<usize> = groups.iter().map(|g| g.len()).collect();
+        if lens != [8, 4, 4, 4, 12] {
+            return Err(anyhow!("Malformed GUID: {}", s));
+        }
+        groups.concat()
+    } else {
+        let bare = inner
+            .strip_prefix("0x")
+            .or_else(|| inner.strip_prefix("0X"))
+            .unwrap_or(inner);
+        if bare.is_empty() || bare.len() > 32 {
+            return Err(anyhow!("Malformed GUID: {}", s));
+        }
+        bare.to_string()
+    };
+
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Malformed GUID: {}", s));
+    }
+    Ok(u128::from_str_radix(&hex, 16)?)
+}
+
+impl WinDev {
+    /// Whether the device passes every criterion of `filter`.
+    pub fn matches(&self, filter: &Filter) -> bool {
+        filter.matches(self)
+    }
+
+    /// Starts a `WinDevBuilder` for constructing a device by hand.
+    pub fn builder() -> WinDevBuilder {
+        WinDevBuilder::default()
+    }
+
+    /// Wraps the device for serializing only the fields of `properties`.
+    pub fn selected<'a>(&'a self, properties: &'a PropertySelection) -> Selected<'a> {
+        Selected {
+            dev: self,
+            properties,
+            decimal_as_string: false,
+        }
+    }
+
+    /// Builds the object `--format json` emits for `properties`, with missing
+    /// values as `Value::Null`, for callers that want to merge or reshape it.
+    /// `guid_decimal` is a string here, since a `Value` number can't hold the
+    /// full 128 bits.
+    pub fn to_json_value(
+        &self,
+        properties: &PropertySelection,
+    ) -> serde_json::Result<serde_json::Value> {
+        serde_json::to_value(Selected {
+            decimal_as_string: true,
+            ..self.selected(properties)
+        })
+    }
+
+    /// A JSON Schema (draft 2020-12) for the array `--format json` prints.
+    /// Fields of properties left out of the selection are optional.
+    /// Kept by hand next to the struct; update it with any serialized field.
+    pub fn json_schema() -> serde_json::Value {
+        let string = || serde_json::json!({ "type": "string" });
+        let nullable = |ty: &str| serde_json::json!({ "type": [ty, "null"] });
+        let strings = || serde_json::json!({ "type": "array", "items": { "type": "string" } });
+        let guid = || {
+            serde_json::json!({
+                "type": "string",
+                "pattern": "^\\{[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}\\}$"
+            })
+        };
+        let mut device = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "friendly_name": nullable("string"),
+                "description": nullable("string"),
+                "guid_decimal": {
+                    "type": "integer",
+                    "description": "The class GUID as a 128-bit number"
+                },
+                "guid_hex": { "type": "string", "pattern": "^0x[0-9a-f]+$" },
+                "class_name": nullable("string"),
+                "class_string": nullable("string"),
+                "hardware_ids": strings(),
+                "compatible_ids": strings(),
+                "manufacturer": nullable("string"),
+                "instance_id": string(),
+                "service": nullable("string"),
+                "enumerator": nullable("string"),
+                "bus_type": { "anyOf": [guid(), { "type": "null" }] },
+                "location": nullable("string"),
+                "driver_key": nullable("string"),
+                "config_flags": nullable("integer"),
+                "capabilities": nullable("integer"),
+                "interfaces": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": { "interface_guid": guid(), "path": string() },
+                        "required": ["interface_guid", "path"],
+                        "additionalProperties": false
+                    }
+                },
+                "diagnostics": strings()
+            },
+            "additionalProperties": false
+        });
+        // the fields of unselected properties are left out, these never are
+        device["required"] = serde_json::json!([
+            "guid_decimal",
+            "guid_hex",
+            "class_name",
+            "instance_id",
+            "interfaces",
+            "diagnostics"
+        ]);
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "print-guid devices",
+            "type": "array",
+            "items": device
+        })
+    }
+
+    /// The class GUID as the `windows` struct, ready to pass back into other
+    /// Win32 calls. Round-trips exactly through the stored `Guid`.
+    #[cfg(windows)]
+    pub fn class_guid(&self) -> windows::core::GUID {
+        self.guid.into()
+    }
+
+    /// `bus_type` as the `windows` struct.
+    #[cfg(windows)]
+    pub fn bus_type_guid(&self) -> Option<windows::core::GUID> {
+        self.bus_type.map(Into::into)
+    }
+
+    /// Whether the device has been disabled, e.g. in Device Manager. Devices
+    /// whose config flags weren't read count as enabled.
+    pub fn disabled(&self) -> bool {
+        self.config_flags
+            .is_some_and(|flags| flags & CONFIGFLAG_DISABLED != 0)
+    }
+
+    /// The names of the capability bits that are set, in bit order. Unknown
+    /// bits are left out; empty when the capabilities weren't read.
+    pub fn capability_names(&self) -> Vec<&'static str> {
+        let bits = self.capabilities.unwrap_or(0);
+        CAPABILITIES
+            .iter()
+            .filter(|(bit, _)| bits & bit != 0)
+            .map(|&(_, name)| name)
+            .collect()
+    }
+
+    /// The text block with the default formatter options, as `Display`
+    /// prints it.
+    pub fn format_text(&self) -> String {
+        self.display(&TextFormat::default()).to_string()
+    }
+
+    /// Renders the text block using the given formatter options.
+    pub fn display<'a>(&'a self, format: &'a TextFormat<'a>) -> Formatted<'a> {
+        Formatted { dev: self, format }
+    }
+}
+
+impl Display for WinDev {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.format_text())
+    }
+}
+
+/// Cuts a registry property buffer down to the `written` bytes SetupAPI
+/// reported. Some implementations report zero, and then the value ends at
+/// the first UTF-16 null, or fills the buffer when there is none.
+pub fn trim_property_buffer(mut buffer: Vec<u8>, written: usize) -> Vec<u8> {
+    let end = match written {
+        0 => buffer
+            .chunks_exact(2)
+            .position(|pair| pair == [0, 0])
+            .map_or(buffer.len(), |i| (i + 1) * 2),
+        size => size,
+    };
+    buffer.truncate(end);
+    buffer
+}
+
+/// Decodes a little-endian UTF-16 registry buffer up to its first null.
+pub fn decode_utf16(buffer: &[u8]) -> String {
+    let wide: Vec<u16> = buffer
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|&c| c != 0) // Remove trailing nulls
+        .collect();
+    String::from_utf16_lossy(&wide)
+}
+
+/// Decodes a little-endian UTF-16 buffer that is already trimmed to the
+/// property's size, dropping only the terminating nulls.
+pub fn decode_utf16_sized(buffer: &[u8]) -> String {
+    let mut wide: Vec<u16> = buffer
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    while wide.last() == Some(&0) {
+        wide.pop();
+    }
+    String::from_utf16_lossy(&wide)
+}
+
+/// Splits a REG_MULTI_SZ buffer into its strings, stopping at the empty
+/// string that marks the end of the list.
+pub fn split_multi_sz(buffer: &[u8]) -> Vec<String> {
+    let wide: Vec<u16> = buffer
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    wide.split(|&c| c == 0)
+        .take_while(|s| !s.is_empty())
+        .map(String::from_utf16_lossy)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISK_DRIVE: u128 = 0x4d36e967_e325_11ce_bfc1_08002be10318;
+
+    #[test]
+    fn formats_known_class_guids() {
+        assert_eq!(
+            format_guid(DISK_DRIVE),
+            "{4d36e967-e325-11ce-bfc1-08002be10318}"
+        );
+        // GUID_DEVCLASS_USB, whose data4 starts with a zero byte
+        assert_eq!(
+            format_guid(0x36fc9e60_c465_11cf_8056_444553540000),
+            "{36fc9e60-c465-11cf-8056-444553540000}"
+        );
+        assert_eq!(format_guid(0), "{00000000-0000-0000-0000-000000000000}");
+    }
+
+    #[test]
+    fn parses_what_format_guid_prints() {
+        let canonical = "{4d36e967-e325-11ce-bfc1-08002be10318}";
+        assert_eq!(parse_guid(canonical).unwrap(), DISK_DRIVE);
+        assert_eq!(parse_guid(&canonical.to_uppercase()).unwrap(), DISK_DRIVE);
+        assert_eq!(
+            parse_guid("4d36e967-e325-11ce-bfc1-08002be10318").unwrap(),
+            DISK_DRIVE
+        );
+        assert_eq!(
+            parse_guid("0x4d36e967e32511cebfc108002be10318").unwrap(),
+            DISK_DRIVE
+        );
+        assert!(parse_guid("{4d36e967-e325-11ce-bfc1}").is_err());
+        assert!(parse_guid("not a guid").is_err());
+    }
+
+    // A registry buffer: little-endian UTF-16 with the terminating null.
+    fn utf16_buffer(text: &str) -> Vec<u8> {
+        text.encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+
+    #[test]
+    fn decodes_non_ascii_utf16() {
+        let name = "Périphérique USB composite – Çağrı";
+        assert_eq!(decode_utf16(&utf16_buffer(name)), name);
+        assert_eq!(decode_utf16_sized(&utf16_buffer(name)), name);
+        // trailing padding after the terminator is ignored
+        let mut padded = utf16_buffer("Gerät");
+        padded.extend([0; 8]);
+        assert_eq!(decode_utf16(&padded), "Gerät");
+        assert_eq!(decode_utf16_sized(&padded), "Gerät");
+    }
+
+    #[test]
+    fn decodes_properties_longer_than_256_bytes() {
+        // what the second SetupAPI call returns once sized to required_size
+        let name = "Very long friendly name ".repeat(12);
+        let buffer = utf16_buffer(&name);
+        assert!(buffer.len() > 256);
+        assert_eq!(decode_utf16_sized(&buffer), name);
+    }
+
+    #[test]
+    fn trims_property_buffers_to_the_written_size() {
+        let mut buffer = utf16_buffer("Disk");
+        buffer.extend([0xcd; 6]);
+        assert_eq!(
+            trim_property_buffer(buffer.clone(), 10),
+            utf16_buffer("Disk")
+        );
+        // a size past the end keeps the whole buffer
+        assert_eq!(trim_property_buffer(buffer.clone(), 64), buffer);
+        // no size reported: up to and including the first null
+        assert_eq!(trim_property_buffer(buffer, 0), utf16_buffer("Disk"));
+    }
+
+    #[test]
+    fn a_missing_terminator_keeps_the_whole_buffer() {
+        let unterminated: Vec<u8> = "Disk".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert_eq!(trim_property_buffer(unterminated.clone(), 0), unterminated);
+        assert_eq!(decode_utf16(&unterminated), "Disk");
+        assert!(trim_property_buffer(Vec::new(), 0).is_empty());
+    }
+
+    // Packs strings into a REG_MULTI_SZ buffer, each null-terminated.
+    fn multi_sz(parts: &[&str]) -> Vec<u8> {
+        parts
+            .iter()
+            .flat_map(|part| part.encode_utf16().chain([0]))
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+
+    #[test]
+    fn splits_multi_sz_at_the_double_null() {
+        let buffer = multi_sz(&["PCI\\VEN_8086&DEV_15B8", "PCI\\VEN_8086", ""]);
+        assert_eq!(
+            split_multi_sz(&buffer),
+            ["PCI\\VEN_8086&DEV_15B8", "PCI\\VEN_8086"]
+        );
+        // padding after the terminator is not read as more strings
+        let mut padded = buffer.clone();
+        padded.extend(multi_sz(&["stale", ""]));
+        assert_eq!(split_multi_sz(&padded).len(), 2);
+    }
+
+    #[test]
+    fn an_empty_segment_ends_the_multi_sz() {
+        // an empty middle string is indistinguishable from the terminator
+        let buffer = multi_sz(&["first", "", "second", ""]);
+        assert_eq!(split_multi_sz(&buffer), ["first"]);
+        assert!(split_multi_sz(&multi_sz(&["", ""])).is_empty());
+        assert!(split_multi_sz(&[]).is_empty());
+    }
+
+    #[test]
+    fn unnamed_devices_sort_last_within_their_class() {
+        let named = |name: &str| WinDev::builder().guid(Guid(DISK_DRIVE)).fname(name).build();
+        let unnamed = WinDev::builder()
+            .guid(Guid(DISK_DRIVE))
+            .desc("Disk drive")
+            .build();
+        let mut devs = [unnamed, named("WD Disk"), named("Samsung SSD")];
+        devs.sort();
+        let names: Vec<Option<&str>> = devs.iter().map(|dev| dev.fname.as_deref()).collect();
+        assert_eq!(names, [Some("Samsung SSD"), Some("WD Disk"), None]);
+    }
+
+    #[test]
+    fn the_class_guid_orders_first() {
+        let net = WinDev::builder()
+            .guid(Guid(0x4d36e972_e325_11ce_bfc1_08002be10318))
+            .fname("Adapter")
+            .build();
+        let disk = WinDev::builder().guid(Guid(DISK_DRIVE)).build();
+        // the lower GUID wins even without a name
+        assert!(disk < net);
+        // same GUID and name: description, then instance ID break the tie
+        let dev = |desc: &str, id: &str| {
+            WinDev::builder()
+                .guid(Guid(DISK_DRIVE))
+                .fname("Disk")
+                .desc(desc)
+                .instance_id(id)
+                .build()
+        };
+        assert!(dev("A", "2") < dev("B", "1"));
+        assert!(dev("A", "1") < dev("A", "2"));
+        assert_eq!(dev("A", "1").cmp(&dev("A", "1")), Ordering::Equal);
+        assert!(dev("A", "1") == dev("A", "1"));
+    }
+
+    #[test]
+    fn json_value_keeps_the_full_guid() {
+        let dev = WinDev::builder()
+            .guid(Guid(DISK_DRIVE))
+            .fname("Disk")
+            .instance_id("SCSI\\DISK\\0")
+            .build();
+        let value = dev.to_json_value(&PropertySelection::all()).unwrap();
+        assert_eq!(value["guid_decimal"], DISK_DRIVE.to_string());
+        assert_eq!(value["guid_hex"], "0x4d36e967e32511cebfc108002be10318");
+        assert_eq!(value["friendly_name"], "Disk");
+        assert_eq!(value["description"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn unselected_properties_are_left_out() {
+        let dev = WinDev::builder()
+            .guid(Guid(DISK_DRIVE))
+            .fname("Disk")
+            .hardware_ids(["GenDisk"])
+            .build();
+        let value = dev.to_json_value(&"name".parse().unwrap()).unwrap();
+        assert_eq!(value["friendly_name"], "Disk");
+        for key in [
+            "description",
+            "hardware_ids",
+            "manufacturer",
+            "config_flags",
+        ] {
+            assert!(value.get(key).is_none(), "{} was serialized", key);
+        }
+        assert_eq!(value["guid_hex"], "0x4d36e967e32511cebfc108002be10318");
+        assert!(value["interfaces"].is_array());
+    }
+
+    #[test]
+    fn the_schema_lists_the_serialized_fields() {
+        let keys = |value: &serde_json::Value| -> Vec<String> {
+            let mut keys: Vec<String> = value.as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+        let schema = WinDev::json_schema();
+        let items = &schema["items"];
+        let dev = WinDev::builder()
+            .guid(Guid(DISK_DRIVE))
+            .fname("Disk")
+            .instance_id("SCSI\\DISK\\0")
+            .build();
+        let full = serde_json::from_str(&serde_json::to_string(&dev).unwrap()).unwrap();
+        assert_eq!(keys(&full), keys(&items["properties"]));
+
+        // with nothing selected only the required fields are left
+        let bare = serde_json::to_string(&dev.selected(&"".parse().unwrap())).unwrap();
+        let mut required: Vec<String> = items["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|key| key.as_str().unwrap().to_string())
+            .collect();
+        required.sort();
+        assert_eq!(keys(&serde_json::from_str(&bare).unwrap()), required);
+    }
+}
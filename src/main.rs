@@ -1,171 +1,1623 @@
 use anyhow::Result;
-use std::fmt::Display;
-use std::mem;
-use std::os::raw::c_void;
-use std::{env::consts::OS, process::exit};
-use windows::Win32::Devices::DeviceAndDriverInstallation::{
-    SetupDiEnumDeviceInfo, SetupDiGetClassDevsA, SetupDiGetDeviceRegistryPropertyA,
-    DIGCF_ALLCLASSES, DIGCF_PRESENT, HDEVINFO, SPDRP_DEVICEDESC, SPDRP_FRIENDLYNAME,
-    SP_DEVINFO_DATA,
+use clap::builder::FalseyValueParser;
+use clap::error::ErrorKind;
+use clap::{ArgAction, CommandFactory, Parser, ValueEnum};
+use print_guid::format::DEFAULT_SEPARATOR;
+use print_guid::{Guid, GuidFormat, PropertySelection, WinDev};
+use regex::Regex;
+use std::path::PathBuf;
+use std::process;
+#[cfg(windows)]
+use windows::{
+    core::{BOOL, HSTRING, PCWSTR},
+    Win32::Foundation::{CloseHandle, ERROR_CANCELLED, INVALID_HANDLE_VALUE},
+    Win32::Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY},
+    Win32::System::Console::SetConsoleCtrlHandler,
+    Win32::System::Threading::{
+        GetCurrentProcess, GetExitCodeProcess, OpenProcessToken, WaitForSingleObject, INFINITE,
+    },
+    Win32::UI::Shell::{ShellExecuteExW, SEE_MASK_NOCLOSEPROCESS, SHELLEXECUTEINFOW},
+    Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL,
 };
-use windows::Win32::Foundation::CloseHandle;
-use windows::Win32::Foundation::INVALID_HANDLE_VALUE;
-use windows::Win32::Security::GetTokenInformation;
-use windows::Win32::Security::TokenElevation;
-use windows::Win32::Security::TOKEN_ELEVATION;
-use windows::Win32::Security::TOKEN_QUERY;
-use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
-
-struct WinDev {
-    fname: Option<String>,
-    desc: Option<String>,
-    guid: u128,
-}
-
-impl Display for WinDev {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let fname = self.fname.clone().unwrap_or("Unkown".to_string());
-        let desc = self.desc.clone().unwrap_or("None".to_string());
-        write!(
-            f,
-            "---------------------------\nDev Name: {}\nDev Desc: {}\nGUID: {}\nGUID (hex): {:#x}\n---------------------------",
-            fname, desc, self.guid, self.guid
-        )
-    }
+// the listing itself, which the tests drive with synthetic devices
+#[cfg(any(windows, test))]
+use {
+    anyhow::{anyhow, Context},
+    log::debug,
+    print_guid::format::{
+        csv_row, dot_edge, dot_node, logfmt_record, powershell_object, table, xml_device,
+        CSV_HEADER, DOT_FOOTER, DOT_HEADER, POWERSHELL_FOOTER, POWERSHELL_HEADER, XML_FOOTER,
+        XML_HEADER,
+    },
+    print_guid::snapshot::{self, SnapshotEntry},
+    print_guid::{DeviceProperty, DeviceSource, Filter, Selected, TextFormat},
+    std::cmp::Ordering,
+    std::collections::{HashMap, HashSet},
+    std::fs::File,
+    std::io::{self, BufWriter, IsTerminal, Write},
+    std::path::Path,
+    std::time::{SystemTime, UNIX_EPOCH},
+};
+#[cfg(windows)]
+use {
+    print_guid::{class_guids_from_name, parent_dev_inst, with_retries, DeviceEnumerator},
+    std::fs::OpenOptions,
+    std::io::BufReader,
+    std::mem,
+    std::os::raw::c_void,
+    std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering},
+    std::thread,
+    std::time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+    Xml,
+    /// Aligned columns of name, description, class and GUID
+    Table,
+    Yaml,
+    /// [PSCustomObject] literals for Invoke-Expression
+    Powershell,
+    /// One logfmt line (key=value pairs) per device for log collectors
+    Eventlog,
+    /// Graphviz graph of the device tree, e.g. for dot -Tpng; implies --tree
+    Dot,
 }
 
-fn get_fname(
-    dev_info_set: HDEVINFO,
-    dev_info_data: *const SP_DEVINFO_DATA,
-) -> Result<Option<String>> {
-    let mut buffer: Vec<u8> = vec![0; 256];
-    let mut required_size: u32 = 0;
+const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  success
+  1  unexpected error (I/O, unknown class name, ...)
+  2  invalid command line arguments
+  3  not running on Windows
+  4  not elevated while --require-admin is set
+  5  failed to get the device list
+  6  error while enumerating devices
+  7  no device matched with --first-only
+  8  could not reach the --machine
+  9  no device of the --probe-guid class";
 
-    unsafe {
-        // when no name return None
-        if SetupDiGetDeviceRegistryPropertyA(
-            dev_info_set,
-            dev_info_data,
-            SPDRP_FRIENDLYNAME,
-            None,
-            Some(&mut buffer),
-            Some(&mut required_size),
-        )
-        .is_err()
-        {
-            return Ok(None);
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum RegexField {
+    Name,
+    Desc,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SortKey {
+    Name,
+    Desc,
+    Guid,
+}
+
+// The default order: class GUID, then instance ID, or the friendly name for
+// devices whose instance ID couldn't be read. Unlike the raw enumeration
+// order this is the same from run to run.
+#[cfg(any(windows, test))]
+fn sort_default(devs: &mut [WinDev]) {
+    devs.sort_by_cached_key(|dev| {
+        let tiebreak = match dev.instance_id.is_empty() {
+            false => Some(dev.instance_id.clone()),
+            true => dev.fname.clone(),
+        };
+        (dev.guid, tiebreak)
+    });
+}
+
+#[cfg(any(windows, test))]
+fn sort_devices(devs: &mut [WinDev], key: SortKey) {
+    // case-insensitive, devices without the field go last
+    let by_text = |a: &Option<String>, b: &Option<String>| match (a, b) {
+        (Some(a), Some(b)) => a.to_lowercase().cmp(&b.to_lowercase()),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    };
+
+    match key {
+        SortKey::Name => devs.sort_by(|a, b| by_text(&a.fname, &b.fname)),
+        SortKey::Desc => devs.sort_by(|a, b| by_text(&a.desc, &b.desc)),
+        SortKey::Guid => devs.sort(),
+    }
+}
+
+// Buckets devices by class GUID, keeping classes in the order they were
+// first seen and devices in enumeration order within each class.
+#[cfg(any(windows, test))]
+fn group_by_class(devs: Vec<WinDev>) -> Vec<WinDev> {
+    let mut groups: Vec<(Guid, Vec<WinDev>)> = Vec::new();
+    for dev in devs {
+        match groups.iter_mut().find(|(guid, _)| *guid == dev.guid) {
+            Some((_, group)) => group.push(dev),
+            None => groups.push((dev.guid, vec![dev])),
         }
     }
+    groups.into_iter().flat_map(|(_, group)| group).collect()
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Field {
+    Name,
+    Desc,
+    Guid,
+    Instance,
+    Class,
+}
 
-    if let Some(null_pos) = buffer.iter().position(|&b| b == 0) {
-        buffer.truncate(null_pos); // Remove trailing nulls
+// The value printed for --field, empty when the device lacks it.
+#[cfg(any(windows, test))]
+fn field_value(dev: &WinDev, field: Field) -> String {
+    match field {
+        Field::Name => dev.fname.clone().unwrap_or_default(),
+        Field::Desc => dev.desc.clone().unwrap_or_default(),
+        Field::Guid => dev.guid.to_string(),
+        Field::Instance => dev.instance_id.clone(),
+        Field::Class => dev.class_name.clone().unwrap_or_default(),
     }
-    let friendly_name = String::from_utf8_lossy(&buffer).to_string();
-    Ok(Some(friendly_name))
 }
 
-fn get_desc(
-    dev_info_set: HDEVINFO,
-    dev_info_data: *const SP_DEVINFO_DATA,
-) -> Result<Option<String>> {
-    let mut buffer: Vec<u8> = vec![0; 256];
-    let mut required_size: u32 = 0;
+#[derive(Clone, Copy, ValueEnum)]
+enum GuidStyle {
+    Decimal,
+    Hex,
+    Canonical,
+    All,
+}
 
-    unsafe {
-        // when no desc return None
-        if SetupDiGetDeviceRegistryPropertyA(
-            dev_info_set,
-            dev_info_data,
-            SPDRP_DEVICEDESC,
-            None,
-            Some(&mut buffer),
-            Some(&mut required_size),
-        )
-        .is_err()
-        {
-            return Ok(None);
+impl From<GuidStyle> for GuidFormat {
+    fn from(style: GuidStyle) -> Self {
+        match style {
+            GuidStyle::Decimal => GuidFormat::Decimal,
+            GuidStyle::Hex => GuidFormat::Hex,
+            GuidStyle::Canonical => GuidFormat::Canonical,
+            GuidStyle::All => GuidFormat::All,
         }
     }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ColorChoice {
+    /// Color when writing to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+/// Why the program stopped, returned from `main` as the process exit code.
+#[derive(Clone, Copy)]
+enum ExitCode {
+    Success = 0,
+    Error = 1,
+    #[cfg(not(windows))]
+    NotWindows = 3,
+    #[cfg(windows)]
+    NotElevated = 4,
+    #[cfg(windows)]
+    DeviceListFailed = 5,
+    #[cfg(any(windows, test))]
+    EnumerationFailed = 6,
+    #[cfg(any(windows, test))]
+    NoMatch = 7,
+    #[cfg(windows)]
+    MachineUnreachable = 8,
+    #[cfg(any(windows, test))]
+    NotFound = 9,
+}
 
-    if let Some(null_pos) = buffer.iter().position(|&b| b == 0) {
-        buffer.truncate(null_pos); // Remove trailing nulls
+impl From<ExitCode> for process::ExitCode {
+    fn from(code: ExitCode) -> Self {
+        process::ExitCode::from(code as u8)
     }
-    let desc = String::from_utf8_lossy(&buffer).to_string();
-    Ok(Some(desc))
+}
+
+/// Lists the devices known to SetupAPI along with their class GUIDs.
+#[derive(Parser)]
+#[command(version, about, after_help = EXIT_CODES_HELP)]
+struct Cli {
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Indent --format json output for reading in a terminal
+    #[arg(long)]
+    json_pretty: bool,
+
+    /// Colorize text output
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto, value_name = "WHEN")]
+    color: ColorChoice,
+
+    /// Text shown for a missing device name in text output
+    #[arg(long, value_name = "TEXT", default_value = "Unknown")]
+    placeholder: String,
+
+    /// Line printed above and below each device in text output; empty for
+    /// none
+    #[arg(long, value_name = "TEXT", default_value = DEFAULT_SEPARATOR)]
+    pretty_separator: String,
+
+    /// Which GUID forms the text output shows
+    #[arg(long, value_enum, default_value_t = GuidStyle::All, value_name = "FORM")]
+    guid_format: GuidStyle,
+
+    /// Show the description when a device has no friendly name
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = ArgAction::Set)]
+    name_fallback: bool,
+
+    /// Comma separated properties to fetch and show: name, desc, mfg, service,
+    /// location, driver, hwids, class, enumerator, bus, compatids, flags, caps
+    #[arg(long, value_name = "LIST", default_value = "name,desc")]
+    properties: PropertySelection,
+
+    /// Truncate table cells longer than this many characters
+    #[arg(long, value_name = "N")]
+    max_col_width: Option<usize>,
+
+    /// Only show devices of this class GUID (brace or bare hex form)
+    #[arg(long, value_name = "GUID")]
+    filter_guid: Option<Guid>,
+
+    /// Skip devices of this class GUID; repeatable, wins over --filter-guid
+    #[arg(long, value_name = "GUID")]
+    exclude_guid: Vec<Guid>,
+
+    /// Only show devices whose class GUID, as a 128-bit number, is at least this
+    #[arg(long, value_name = "GUID")]
+    min_guid: Option<Guid>,
+
+    /// Only show devices whose class GUID, as a 128-bit number, is at most this
+    #[arg(long, value_name = "GUID")]
+    max_guid: Option<Guid>,
+
+    /// Only show devices of the class GUIDs listed in this file, one per line
+    /// with # comments; combined with --filter-guid, either may match
+    #[arg(long, value_name = "PATH")]
+    class_file: Option<PathBuf>,
+
+    /// Only show devices of this installer class (e.g. USB, Net)
+    #[arg(long, value_name = "NAME")]
+    class: Option<String>,
+
+    /// Only show devices whose friendly name contains this text (case-insensitive)
+    #[arg(long, value_name = "TEXT")]
+    name_contains: Option<String>,
+
+    /// Only show devices with a hardware ID containing this text
+    /// (case-insensitive), e.g. VID_046D&PID_C52B
+    #[arg(long, value_name = "TEXT")]
+    contains_hwid: Option<String>,
+
+    /// Only show devices whose name (see --regex-field) matches this pattern
+    #[arg(long, value_name = "PATTERN", value_parser = Regex::new)]
+    regex: Option<Regex>,
+
+    /// Field tested by --regex
+    #[arg(long, value_enum, default_value_t = RegexField::Name, value_name = "FIELD")]
+    regex_field: RegexField,
+
+    /// Sort the devices by this field instead of the default class GUID and
+    /// instance ID order. The default order is skipped for --format ndjson,
+    /// --limit and --first-only, which print devices as they are found
+    #[arg(long, value_enum, value_name = "FIELD")]
+    sort: Option<SortKey>,
+
+    /// Print devices in raw enumeration order as they are found
+    #[arg(long, conflicts_with = "sort")]
+    no_sort: bool,
+
+    /// List devices in sections under their device class
+    #[arg(long)]
+    group_by_class: bool,
+
+    /// Show text output as a tree of parent and child devices
+    #[arg(long, conflicts_with_all = ["sort", "group_by_class"])]
+    tree: bool,
+
+    /// Drop devices with the same name, description and class GUID as an earlier one
+    #[arg(long)]
+    unique: bool,
+
+    /// Only show devices that have been disabled
+    #[arg(long, conflicts_with = "only_enabled")]
+    only_disabled: bool,
+
+    /// Only show devices that haven't been disabled
+    #[arg(long)]
+    only_enabled: bool,
+
+    /// Skip devices with neither a friendly name nor a description
+    #[arg(long)]
+    hide_empty: bool,
+
+    /// Keep only the first device for each value of this field; devices
+    /// without the field are all kept
+    #[arg(long, value_enum, value_name = "FIELD")]
+    dedupe_by: Option<Field>,
+
+    /// Only list devices that are currently connected (default)
+    #[arg(long, visible_alias = "present", conflicts_with = "all")]
+    present_only: bool,
+
+    /// Also list installed devices that are not currently connected
+    #[arg(long, visible_aliases = ["all-installed", "no-present"])]
+    all: bool,
+
+    /// Stop after this many devices have been printed, in enumeration order
+    /// unless --sort is given
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Print only the first matching device, exiting with 7 when none match;
+    /// the walk stops at the first match unless --sort is given
+    #[arg(long, conflicts_with = "limit")]
+    first_only: bool,
+
+    /// Stop at the first property that can't be read
+    #[arg(long, conflicts_with = "continue_on_error")]
+    fail_fast: bool,
+
+    /// Record unreadable properties on the device and keep going (default)
+    #[arg(long)]
+    continue_on_error: bool,
+
+    /// Treat every unreadable property as missing, only logging it at debug
+    /// level. For partial SetupAPI implementations such as Wine's, which are
+    /// supported on a best-effort basis
+    #[arg(long, env = "PRINT_GUID_LENIENT", value_parser = FalseyValueParser::new(), conflicts_with = "fail_fast")]
+    lenient: bool,
+
+    /// Print just --field of each device, separated by NUL bytes for xargs -0
+    #[arg(long, short = '0', conflicts_with_all = ["count", "diff"])]
+    null_separated: bool,
+
+    /// Print only this field, one device per line; devices without it are
+    /// skipped. With --null-separated it defaults to instance
+    #[arg(long, value_enum, value_name = "FIELD", conflicts_with_all = ["count", "count_by_class", "diff"])]
+    field: Option<Field>,
+
+    /// Print only the number of matching devices
+    #[arg(long)]
+    count: bool,
+
+    /// Print only how many devices each class has, most common first
+    #[arg(long, conflicts_with_all = ["count", "null_separated", "diff"])]
+    count_by_class: bool,
+
+    /// Print a per-class summary to stderr after the devices
+    #[arg(long)]
+    stats: bool,
+
+    /// Print only the --stats summary, to the output instead of stderr
+    #[arg(long, conflicts_with_all = ["count", "count_by_class", "null_separated", "field", "diff"])]
+    summary_only: bool,
+
+    /// Exit instead of warning when not running elevated
+    #[arg(long)]
+    require_admin: bool,
+
+    /// When not elevated, run again as administrator through a UAC prompt.
+    /// The elevated run gets a console window of its own, so combine this
+    /// with --output to keep the results
+    #[arg(long)]
+    elevate: bool,
+
+    // Marks the process started by --elevate, so it never relaunches itself
+    // again
+    #[arg(long, hide = true)]
+    elevated_relaunch: bool,
+
+    /// Silence warnings and informational messages (fatal errors are still shown)
+    #[arg(long, short)]
+    quiet: bool,
+
+    /// Also list each device's interfaces of this interface class GUID with
+    /// the device paths to open them
+    #[arg(long, value_name = "GUID")]
+    interfaces: Option<Guid>,
+
+    /// List the devices of this remote machine instead of the local one. Needs
+    /// administrator rights there and the Remote Registry service running;
+    /// newer Windows versions no longer allow remote device access. The device
+    /// tree can't be read remotely, so --tree and --format dot are rejected,
+    /// and --class and the class names shown come from the local machine
+    #[arg(long, value_name = "NAME", conflicts_with = "tree")]
+    machine: Option<String>,
+
+    /// Retry getting the device list this many times if it fails
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retries: u32,
+
+    /// Write the results to this file instead of stdout
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Add to the end of the --output file, after a timestamped separator,
+    /// instead of replacing it
+    #[arg(long, requires = "output")]
+    output_append: bool,
+
+    /// Save the listed devices to this JSON file for a later --diff
+    #[arg(long, value_name = "FILE")]
+    snapshot: Option<PathBuf>,
+
+    /// Print only the devices added (+) or removed (-) since this snapshot
+    #[arg(long, value_name = "FILE")]
+    diff: Option<PathBuf>,
+
+    /// List the devices again every this many seconds, with a timestamped
+    /// separator between runs, until Ctrl-C
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    watch: Option<u64>,
+
+    /// With --watch, print the full list once and then only the devices
+    /// added (+) or removed (-) since the previous run
+    #[arg(long, requires = "watch")]
+    watch_diff: bool,
+
+    /// Print nothing and exit with 0 if a device of this class GUID exists, 9
+    /// if not; the walk stops at the first one found
+    #[arg(
+        long,
+        value_name = "GUID",
+        conflicts_with_all = ["filter_guid", "class_file", "output", "snapshot", "diff", "watch", "stats", "limit", "first_only"]
+    )]
+    probe_guid: Option<Guid>,
+
+    /// Print the crate version, windows crate version and target, then exit
+    #[arg(long)]
+    build_info: bool,
+
+    /// Print a JSON Schema for the --format json output, then exit
+    #[arg(long)]
+    json_schema: bool,
+}
+
+fn print_build_info(args: &Cli) {
+    println!("print-guid {}", env!("CARGO_PKG_VERSION"));
+    println!("windows crate: {}", env!("BUILD_WINDOWS_VERSION"));
+    println!("target: {}", env!("BUILD_TARGET"));
+    let devices = match args.all {
+        true => "all installed (without DIGCF_PRESENT)",
+        false => "present only (DIGCF_PRESENT)",
+    };
+    println!("devices: {}", devices);
+}
+
+/// Whether the process token is elevated. `Unknown` means the token could
+/// not be queried at all, which is not the same as running unelevated.
+#[cfg(windows)]
+#[derive(Clone, Copy)]
+enum Elevation {
+    Elevated,
+    NotElevated,
+    Unknown,
 }
 
 // This code snippet is derived from "is-root" by "John Meow"
 // Original repository: https://gitlab.com/caralice/is-root
-fn is_root() -> Result<bool> {
+#[cfg(windows)]
+fn elevation() -> Result<Elevation> {
     let mut token = INVALID_HANDLE_VALUE;
-    let mut elevated = false;
+    let mut elevation = Elevation::Unknown;
     unsafe {
         if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token).is_ok() {
-            let mut elevation: TOKEN_ELEVATION = mem::zeroed();
+            let mut info: TOKEN_ELEVATION = mem::zeroed();
             let mut size = mem::size_of::<TOKEN_ELEVATION>().try_into().unwrap();
             if GetTokenInformation(
                 token,
                 TokenElevation,
-                Some(&mut elevation as *mut TOKEN_ELEVATION as *mut c_void),
+                Some(&mut info as *mut TOKEN_ELEVATION as *mut c_void),
                 size,
                 &mut size,
             )
             .is_ok()
             {
-                elevated = elevation.TokenIsElevated != 0;
+                elevation = match info.TokenIsElevated != 0 {
+                    true => Elevation::Elevated,
+                    false => Elevation::NotElevated,
+                };
             }
         }
         if token != INVALID_HANDLE_VALUE {
             CloseHandle(token)?;
         }
     }
-    Ok(elevated)
+    Ok(elevation)
 }
 
-fn main() -> Result<()> {
-    if OS != "windows" {
-        println!("OS isn't windows!");
-        exit(1);
+// Quotes an argument so CommandLineToArgvW and the C runtime split it back
+// out unchanged: backslashes are literal unless they precede a quote.
+#[cfg(windows)]
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '"']) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                continue;
+            }
+            // escape the pending backslashes and the quote itself
+            '"' => quoted.push_str(&"\\".repeat(backslashes * 2 + 1)),
+            _ => quoted.push_str(&"\\".repeat(backslashes)),
+        }
+        backslashes = 0;
+        quoted.push(c);
     }
+    // doubled so they don't escape the closing quote
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+// Starts this program again with the same arguments through the "runas"
+// verb, waits for it and exits with its exit code. Returns NotElevated when
+// the UAC prompt is declined.
+#[cfg(windows)]
+fn relaunch_elevated() -> Result<ExitCode> {
+    let exe = std::env::current_exe().context("Failed to locate the executable")?;
+    let mut params: Vec<String> = std::env::args_os()
+        .skip(1)
+        .map(|arg| quote_arg(&arg.to_string_lossy()))
+        .collect();
+    params.push("--elevated-relaunch".to_string());
 
-    if !is_root()? {
-        println!("This program needs root priviledges");
-        exit(1);
+    let verb = HSTRING::from("runas");
+    let file = HSTRING::from(exe.as_os_str());
+    let params = HSTRING::from(params.join(" "));
+    // without it the child starts in System32 and relative --output,
+    // --snapshot and --class-file paths point there
+    let dir = std::env::current_dir().context("Failed to read the current directory")?;
+    let dir = HSTRING::from(dir.as_os_str());
+    let mut info = SHELLEXECUTEINFOW {
+        cbSize: mem::size_of::<SHELLEXECUTEINFOW>() as u32,
+        fMask: SEE_MASK_NOCLOSEPROCESS,
+        lpVerb: PCWSTR(verb.as_ptr()),
+        lpFile: PCWSTR(file.as_ptr()),
+        lpParameters: PCWSTR(params.as_ptr()),
+        lpDirectory: PCWSTR(dir.as_ptr()),
+        nShow: SW_SHOWNORMAL.0,
+        ..Default::default()
+    };
+    match unsafe { ShellExecuteExW(&mut info) } {
+        Ok(()) => {}
+        Err(e) if e.code() == ERROR_CANCELLED.to_hresult() => {
+            eprintln!("Elevation was declined");
+            return Ok(ExitCode::NotElevated);
+        }
+        Err(e) => return Err(anyhow!("Failed to relaunch elevated: {}", e)),
     }
 
-    let dev_info_set =
-        unsafe { SetupDiGetClassDevsA(None, None, None, DIGCF_ALLCLASSES | DIGCF_PRESENT) }?;
+    let mut code = 0;
+    unsafe {
+        WaitForSingleObject(info.hProcess, INFINITE);
+        let status = GetExitCodeProcess(info.hProcess, &mut code);
+        CloseHandle(info.hProcess)?;
+        status.context("Failed to get the elevated run's exit code")?;
+    }
+    process::exit(code as i32)
+}
 
-    if dev_info_set.is_invalid() {
-        println!("Failed to get device list");
-        exit(1)
+// Reads a --class-file: one GUID per line in any form `Guid` parses, with
+// blank lines and anything after a # ignored.
+#[cfg(any(windows, test))]
+fn read_class_file(path: &Path) -> Result<Vec<Guid>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read class file {}", path.display()))?;
+    let mut guids = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let guid = line
+            .parse()
+            .with_context(|| format!("{}:{}", path.display(), number + 1))?;
+        guids.push(guid);
     }
+    Ok(guids)
+}
 
-    let mut dev_info_data = SP_DEVINFO_DATA {
-        cbSize: std::mem::size_of::<SP_DEVINFO_DATA>() as u32,
-        ..Default::default()
+// The closest ancestor of `inst` among the listed devices, walking up
+// through the devnodes that were filtered out.
+#[cfg(any(windows, test))]
+fn shown_ancestor(
+    mut inst: u32,
+    shown: &HashSet<u32>,
+    parent_of: &dyn Fn(u32) -> Option<u32>,
+) -> Option<u32> {
+    while let Some(parent) = parent_of(inst) {
+        if shown.contains(&parent) {
+            return Some(parent);
+        }
+        inst = parent;
+    }
+    None
+}
+
+// Prints devices indented under their parents. Devnodes that were filtered
+// out are not shown, and their children move up to the nearest shown
+// ancestor.
+#[cfg(any(windows, test))]
+fn write_tree(
+    out: &mut dyn Write,
+    devs: &[WinDev],
+    format: &TextFormat,
+    parent_of: &dyn Fn(u32) -> Option<u32>,
+) -> io::Result<()> {
+    let shown: HashSet<u32> = devs.iter().map(|dev| dev.dev_inst).collect();
+    let mut children: HashMap<Option<u32>, Vec<&WinDev>> = HashMap::new();
+    for dev in devs {
+        let parent = shown_ancestor(dev.dev_inst, &shown, parent_of);
+        children.entry(parent).or_default().push(dev);
+    }
+    write_subtree(out, None, 0, &children, format)
+}
+
+#[cfg(any(windows, test))]
+fn write_subtree(
+    out: &mut dyn Write,
+    parent: Option<u32>,
+    depth: usize,
+    children: &HashMap<Option<u32>, Vec<&WinDev>>,
+    format: &TextFormat,
+) -> io::Result<()> {
+    for dev in children.get(&parent).into_iter().flatten() {
+        writeln!(
+            out,
+            "{:indent$}{} [{}]",
+            "",
+            format.name(dev),
+            format.class(dev.class_name.as_deref().unwrap_or("Unknown class")),
+            indent = depth * 2
+        )?;
+        write_subtree(out, Some(dev.dev_inst), depth + 1, children, format)?;
+    }
+    Ok(())
+}
+
+// Writes the devices as a DOT graph with an edge from each device to its
+// nearest listed ancestor, skipping devnodes that were filtered out like
+// the text tree does.
+#[cfg(any(windows, test))]
+fn write_dot(
+    out: &mut dyn Write,
+    devs: &[WinDev],
+    format: &TextFormat,
+    parent_of: &dyn Fn(u32) -> Option<u32>,
+) -> io::Result<()> {
+    let shown: HashSet<u32> = devs.iter().map(|dev| dev.dev_inst).collect();
+    writeln!(out, "{}", DOT_HEADER)?;
+    for dev in devs {
+        writeln!(out, "{}", dot_node(dev, format.name(dev)))?;
+    }
+    for dev in devs {
+        if let Some(parent) = shown_ancestor(dev.dev_inst, &shown, parent_of) {
+            writeln!(out, "{}", dot_edge(parent, dev.dev_inst))?;
+        }
+    }
+    writeln!(out, "{}", DOT_FOOTER)
+}
+
+/// Totals gathered for `--stats`.
+#[cfg(any(windows, test))]
+#[derive(Default)]
+struct Stats {
+    total: usize,
+    unnamed: usize,
+    per_class: HashMap<String, usize>,
+}
+
+#[cfg(any(windows, test))]
+impl Stats {
+    fn add(&mut self, dev: &WinDev) {
+        self.total += 1;
+        if dev.fname.is_none() {
+            self.unnamed += 1;
+        }
+        let class = dev.class_name.as_deref().unwrap_or("Unknown class");
+        *self.per_class.entry(class.to_string()).or_default() += 1;
+    }
+
+    // most common classes first, ties broken by name
+    fn write_classes(&self, out: &mut dyn Write, indent: &str) -> io::Result<()> {
+        let mut classes: Vec<(&String, &usize)> = self.per_class.iter().collect();
+        classes.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        let width = classes
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        for (name, count) in classes {
+            writeln!(out, "{}{:width$}  {}", indent, name, count, width = width)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "Devices: {}", self.total)?;
+        writeln!(out, "Without a friendly name: {}", self.unnamed)?;
+        writeln!(out, "Per class:")?;
+        self.write_classes(out, "  ")
+    }
+
+    fn print(&self) -> io::Result<()> {
+        self.write(&mut io::stderr())
+    }
+}
+
+// Formats the current UTC time as e.g. 2024-05-01 13:45:07 UTC.
+#[cfg(any(windows, test))]
+fn utc_timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // days since 1970-01-01 to a civil date, after Howard Hinnant's
+    // days_from_civil inverse
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// Set from the console control handler when Ctrl-C or Ctrl-Break is pressed
+// during --watch.
+#[cfg(windows)]
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(windows)]
+unsafe extern "system" fn on_ctrl_c(_ctrl_type: u32) -> BOOL {
+    INTERRUPTED.store(true, AtomicOrdering::SeqCst);
+    // handled, don't let the default handler kill the process
+    true.into()
+}
+
+// Sleeps for `interval`, waking early when interrupted. Returns whether the
+// watch should go on.
+#[cfg(windows)]
+fn sleep_unless_interrupted(interval: Duration) -> bool {
+    let deadline = Instant::now() + interval;
+    while !INTERRUPTED.load(AtomicOrdering::SeqCst) {
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(100)));
+    }
+    false
+}
+
+fn main() -> process::ExitCode {
+    env_logger::init();
+    let args = Cli::parse();
+    // clap can't express a conflict with one value of --format
+    if args.json_pretty && matches!(args.format, Format::Ndjson) {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--json-pretty can't be used with --format ndjson",
+            )
+            .exit();
+    }
+    // the parent links come from the local Config Manager
+    if args.machine.is_some() && matches!(args.format, Format::Dot) {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--machine can't be used with --format dot",
+            )
+            .exit();
+    }
+    if let (Some(min), Some(max)) = (args.min_guid, args.max_guid) {
+        if min > max {
+            Cli::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("--min-guid {} is greater than --max-guid {}", min, max),
+                )
+                .exit();
+        }
+    }
+
+    if args.build_info {
+        print_build_info(&args);
+        return ExitCode::Success.into();
+    }
+    if args.json_schema {
+        let schema = WinDev::json_schema();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&schema).expect("schema serializes")
+        );
+        return ExitCode::Success.into();
+    }
+
+    match run(&args) {
+        Ok(code) => code.into(),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::Error.into()
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn run(_args: &Cli) -> Result<ExitCode> {
+    eprintln!("OS isn't windows!");
+    Ok(ExitCode::NotWindows)
+}
+
+#[cfg(windows)]
+fn run(args: &Cli) -> Result<ExitCode> {
+    // a probe answers through its exit code alone
+    let quiet = args.quiet || args.probe_guid.is_some();
+    match elevation()? {
+        Elevation::Elevated => {}
+        // a relaunched process that still isn't elevated carries on as is
+        Elevation::NotElevated if args.elevate && !args.elevated_relaunch => {
+            return relaunch_elevated();
+        }
+        Elevation::NotElevated if args.require_admin => {
+            eprintln!("This program needs root priviledges");
+            return Ok(ExitCode::NotElevated);
+        }
+        Elevation::NotElevated if !quiet => {
+            eprintln!("Warning: not running elevated, some device properties may be missing");
+        }
+        // don't claim missing privileges when we simply couldn't tell
+        Elevation::Unknown if !quiet => {
+            eprintln!(
+                "Warning: could not determine elevation, some device properties may be missing"
+            );
+        }
+        Elevation::NotElevated | Elevation::Unknown => {}
+    }
+
+    // read before anything is written, --snapshot may point at the same file
+    let previous: Option<Vec<SnapshotEntry>> = match &args.diff {
+        Some(path) => {
+            let file = File::open(path)
+                .with_context(|| format!("Failed to open snapshot {}", path.display()))?;
+            let entries = serde_json::from_reader(BufReader::new(file))
+                .with_context(|| format!("Malformed snapshot {}", path.display()))?;
+            Some(entries)
+        }
+        None => None,
+    };
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) if args.output_append => {
+            let file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            Box::new(BufWriter::new(file))
+        }
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None if args.probe_guid.is_some() => Box::new(io::sink()),
+        None => Box::new(io::stdout()),
     };
+    // --watch starts every run with one of these already
+    if args.output_append && args.watch.is_none() {
+        writeln!(out, "=== {} ===", utc_timestamp())?;
+    }
 
-    let mut index = 0;
+    match args.watch {
+        Some(seconds) => watch(args, &mut *out, Duration::from_secs(seconds), previous),
+        None => list_devices(args, &mut *out, previous.as_deref(), &mut Vec::new()),
+    }
+}
+
+// Lists the devices every `interval` until Ctrl-C. Each run opens new device
+// info sets, so SetupAPI's view is fresh and the old handles are released.
+#[cfg(windows)]
+fn watch(
+    args: &Cli,
+    out: &mut dyn Write,
+    interval: Duration,
+    mut previous: Option<Vec<SnapshotEntry>>,
+) -> Result<ExitCode> {
+    unsafe { SetConsoleCtrlHandler(Some(on_ctrl_c), true) }
+        .context("Failed to install the Ctrl-C handler")?;
     loop {
-        if let Err(e) = unsafe { SetupDiEnumDeviceInfo(dev_info_set, index, &mut dev_info_data) } {
-            // Exit code for no more devices
-            match e.to_string().contains("0x80070103") {
-                true => break Ok(()),
-                false => {
-                    println!("Error occurred: {}", e);
-                    exit(1);
+        if previous.is_none() {
+            writeln!(out, "=== {} ===", utc_timestamp())?;
+        }
+        let mut entries = Vec::new();
+        match list_devices(args, out, previous.as_deref(), &mut entries)? {
+            ExitCode::Success | ExitCode::NoMatch => {}
+            code => return Ok(code),
+        }
+        out.flush()?;
+        if args.watch_diff {
+            previous = Some(entries);
+        }
+        if !sleep_unless_interrupted(interval) {
+            return Ok(ExitCode::Success);
+        }
+    }
+}
+
+// Builds the filter from the command line. `class_guids` are the classes
+// --class resolved to.
+#[cfg(any(windows, test))]
+fn build_filter(args: &Cli, class_guids: Vec<Guid>) -> Result<Filter> {
+    // a probe is a class filter that stops at the first match
+    let filter_guid = args.filter_guid.or(args.probe_guid);
+    // the file and --filter-guid together make one allowlist
+    let allowed_guids = match &args.class_file {
+        Some(path) => {
+            let mut guids = read_class_file(path)?;
+            guids.extend(filter_guid);
+            Some(guids)
+        }
+        None => None,
+    };
+    let regex = |field: RegexField| args.regex.clone().filter(|_| args.regex_field == field);
+    Ok(Filter {
+        exclude_guids: args.exclude_guid.clone(),
+        guid: filter_guid.filter(|_| allowed_guids.is_none()),
+        class_guids,
+        allowed_guids,
+        min_guid: args.min_guid,
+        max_guid: args.max_guid,
+        name_contains: args.name_contains.clone(),
+        name_regex: regex(RegexField::Name),
+        desc_regex: regex(RegexField::Desc),
+        disabled: match (args.only_disabled, args.only_enabled) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        },
+        hwid_contains: args.contains_hwid.clone(),
+    })
+}
+
+// The properties to read: the selected ones plus whatever the filters,
+// sorting and output format look at.
+#[cfg(any(windows, test))]
+fn fetched_properties(args: &Cli) -> PropertySelection {
+    // filters, sorting, --unique and snapshots look at name and description,
+    // so read those even when they aren't shown
+    let snapshots = args.snapshot.is_some() || args.diff.is_some() || args.watch_diff;
+    let mut fetched = args.properties.clone();
+    if args.name_contains.is_some()
+        || args.hide_empty
+        || args.unique
+        || args.stats
+        || args.summary_only
+        || snapshots
+        || matches!(args.field, Some(Field::Name))
+        || matches!(args.dedupe_by, Some(Field::Name))
+        || (args.regex.is_some() && matches!(args.regex_field, RegexField::Name))
+        || matches!(args.sort, Some(SortKey::Name))
+    {
+        fetched.insert(DeviceProperty::FriendlyName);
+    }
+    if args.unique
+        || args.hide_empty
+        || snapshots
+        || matches!(args.field, Some(Field::Desc))
+        || matches!(args.dedupe_by, Some(Field::Desc))
+        || (args.name_fallback && args.properties.contains(DeviceProperty::FriendlyName))
+        || (args.regex.is_some() && matches!(args.regex_field, RegexField::Desc))
+        || matches!(args.sort, Some(SortKey::Desc))
+    {
+        fetched.insert(DeviceProperty::Description);
+    }
+    // the column formats print name and description whatever is selected
+    if matches!(
+        args.format,
+        Format::Csv | Format::Table | Format::Xml | Format::Powershell | Format::Eventlog
+    ) {
+        fetched.insert(DeviceProperty::FriendlyName);
+        fetched.insert(DeviceProperty::Description);
+    }
+    // PowerShell objects carry the hardware IDs as well
+    if args.contains_hwid.is_some() || matches!(args.format, Format::Powershell) {
+        fetched.insert(DeviceProperty::HardwareIds);
+    }
+    if args.only_disabled || args.only_enabled {
+        fetched.insert(DeviceProperty::ConfigFlags);
+    }
+    fetched
+}
+
+// One listing of the devices. Against `previous` only the changes are
+// printed; `entries` receives the listed devices when snapshots are in use.
+#[cfg(windows)]
+fn list_devices(
+    args: &Cli,
+    out: &mut dyn Write,
+    previous: Option<&[SnapshotEntry]>,
+    entries: &mut Vec<SnapshotEntry>,
+) -> Result<ExitCode> {
+    let class_guids: Vec<Guid> = match &args.class {
+        Some(name) => {
+            let guids = class_guids_from_name(name)?;
+            if guids.is_empty() {
+                return Err(anyhow!("No device class named {}", name));
+            }
+            guids.into_iter().map(Guid::from).collect()
+        }
+        None => Vec::new(),
+    };
+    let filter = build_filter(args, class_guids)?;
+
+    let fetched = fetched_properties(args);
+
+    let present_only = !args.all;
+
+    // scope the device info sets to the requested classes when we know them
+    let scopes: Vec<Guid> = match (&filter.allowed_guids, filter.guid) {
+        (Some(allowed), _) => {
+            let mut allowed = allowed.clone();
+            allowed.sort();
+            allowed.dedup();
+            allowed
+        }
+        (None, Some(guid)) => vec![guid],
+        (None, None) => filter.class_guids.clone(),
+    };
+    let machine = args.machine.as_deref();
+    let enumerators = match scopes.is_empty() {
+        true => with_retries(args.retries, || {
+            DeviceEnumerator::open(None, present_only, machine)
+        })
+        .map(|e| vec![e]),
+        false => scopes
+            .iter()
+            .map(|&guid| {
+                with_retries(args.retries, || {
+                    DeviceEnumerator::open(Some(&guid.into()), present_only, machine)
+                })
+            })
+            .collect::<Result<Vec<_>>>(),
+    };
+    let sources: Vec<Box<dyn DeviceSource>> = match enumerators {
+        Ok(enumerators) => enumerators
+            .into_iter()
+            .map(|e| {
+                let e = e
+                    .with_properties(fetched.clone())
+                    .with_fail_fast(args.fail_fast)
+                    .with_lenient(args.lenient);
+                let e = match args.interfaces {
+                    Some(guid) => e.with_interfaces(guid.into()),
+                    None => e,
+                };
+                Box::new(e) as Box<dyn DeviceSource>
+            })
+            .collect(),
+        Err(e) if machine.is_some() => {
+            eprintln!("{}", e);
+            return Ok(ExitCode::MachineUnreachable);
+        }
+        Err(e) => {
+            eprintln!("Failed to get device list: {}", e);
+            return Ok(ExitCode::DeviceListFailed);
+        }
+    };
+
+    render(
+        args,
+        &sources,
+        &filter,
+        &parent_dev_inst,
+        out,
+        previous,
+        entries,
+    )
+}
+
+// Filters, orders and prints the devices of `sources`. Kept apart from the
+// SetupAPI setup so it runs on synthetic devices too; `parent_of` gives the
+// devnode tree for --tree and --format dot.
+#[cfg(any(windows, test))]
+fn render(
+    args: &Cli,
+    sources: &[Box<dyn DeviceSource>],
+    filter: &Filter,
+    parent_of: &dyn Fn(u32) -> Option<u32>,
+    out: &mut dyn Write,
+    previous: Option<&[SnapshotEntry]>,
+    entries: &mut Vec<SnapshotEntry>,
+) -> Result<ExitCode> {
+    let probing = args.probe_guid.is_some();
+    let quiet = args.quiet || probing;
+    // nothing at all, not even the headers and wrappers of the formats
+    if args.limit == Some(0) {
+        return Ok(ExitCode::Success);
+    }
+    let snapshots = args.snapshot.is_some() || args.diff.is_some() || args.watch_diff;
+    let keep = |dev: &WinDev| filter.matches(dev);
+
+    let color = match args.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => args.output.is_none() && io::stdout().is_terminal(),
+    };
+    let text_format = TextFormat {
+        placeholder: &args.placeholder,
+        color,
+        name_fallback: args.name_fallback,
+        guid_format: args.guid_format.into(),
+        properties: args.properties.clone(),
+        separator: &args.pretty_separator,
+    };
+
+    let field_only = args.null_separated || args.field.is_some();
+    let field = args.field.unwrap_or(Field::Instance);
+    if !args.count
+        && !args.count_by_class
+        && !args.summary_only
+        && !field_only
+        && previous.is_none()
+    {
+        match args.format {
+            Format::Csv => writeln!(out, "{}", CSV_HEADER)?,
+            Format::Xml => writeln!(out, "{}", XML_HEADER)?,
+            Format::Powershell => writeln!(out, "{}", POWERSHELL_HEADER)?,
+            _ => {}
+        }
+    }
+
+    let mut seen: HashSet<(Option<String>, Option<String>, Guid)> = HashSet::new();
+    let mut seen_keys: HashSet<String> = HashSet::new();
+    let mut duplicates: usize = 0;
+    let mut hidden: usize = 0;
+    let mut devices: Box<dyn Iterator<Item = Result<WinDev>>> = Box::new(
+        sources
+            .iter()
+            .flat_map(|source| source.iter_devices())
+            .filter(|dev| dev.as_ref().map_or(true, keep))
+            .filter(|dev| match dev {
+                Ok(dev) if args.hide_empty && dev.fname.is_none() && dev.desc.is_none() => {
+                    hidden += 1;
+                    false
+                }
+                _ => true,
+            })
+            .filter(|dev| {
+                let Ok(dev) = dev else { return true };
+                let duplicate = (args.unique
+                    && !seen.insert((dev.fname.clone(), dev.desc.clone(), dev.guid)))
+                    || args.dedupe_by.is_some_and(|field| {
+                        let key = field_value(dev, field);
+                        !key.is_empty() && !seen_keys.insert(key)
+                    });
+                if duplicate {
+                    duplicates += 1;
+                }
+                !duplicate
+            }),
+    );
+
+    // sorting and grouping need the whole list up front. The default order
+    // isn't worth that when the output streams or the walk stops early.
+    let default_sort = !args.no_sort
+        && !probing
+        && !args.first_only
+        && args.limit.is_none()
+        && !matches!(args.format, Format::Ndjson);
+    if default_sort || args.sort.is_some() || args.group_by_class {
+        let mut buffered = match devices.collect::<Result<Vec<_>>>() {
+            Ok(devs) => devs,
+            Err(e) => {
+                eprintln!("Error occurred: {}", e);
+                return Ok(ExitCode::EnumerationFailed);
+            }
+        };
+        match args.sort {
+            Some(key) => sort_devices(&mut buffered, key),
+            None if default_sort => sort_default(&mut buffered),
+            None => {}
+        }
+        if args.group_by_class {
+            buffered = group_by_class(buffered);
+        }
+        devices = Box::new(buffered.into_iter().map(Ok));
+    }
+
+    // taking one stops the enumeration as soon as something matches
+    let limit = match args.first_only || probing {
+        true => Some(1),
+        false => args.limit,
+    };
+    if let Some(limit) = limit {
+        devices = Box::new(devices.take(limit));
+    }
+
+    let mut devs: Vec<WinDev> = Vec::new();
+    let mut count: usize = 0;
+    let mut matched: usize = 0;
+    let mut stats = Stats::default();
+    let mut current_class: Option<Guid> = None;
+    for dev in devices {
+        if let Ok(dev) = &dev {
+            matched += 1;
+            stats.add(dev);
+            if snapshots {
+                entries.push(dev.into());
+            }
+        }
+        match dev {
+            Ok(_) if args.count => count += 1,
+            // tallied in stats above
+            Ok(_) if args.count_by_class || args.summary_only => {}
+            Ok(dev) if args.null_separated => write!(out, "{}\0", field_value(&dev, field))?,
+            Ok(dev) if field_only => {
+                let value = field_value(&dev, field);
+                if !value.is_empty() {
+                    writeln!(out, "{}", value)?;
                 }
             }
+            // replaced by the change list below
+            Ok(_) if previous.is_some() => {}
+            Ok(dev) => match args.format {
+                Format::Text if args.tree => devs.push(dev),
+                Format::Text => {
+                    if args.group_by_class && current_class != Some(dev.guid) {
+                        let class_name = dev.class_name.as_deref().unwrap_or("Unknown class");
+                        writeln!(
+                            out,
+                            "=== {} {} ===",
+                            text_format.class(class_name),
+                            dev.guid
+                        )?;
+                        current_class = Some(dev.guid);
+                    }
+                    writeln!(out, "{}", dev.display(&text_format))?
+                }
+                // these need every device before printing anything
+                Format::Json | Format::Table | Format::Yaml | Format::Dot => devs.push(dev),
+                Format::Csv => writeln!(out, "{}", csv_row(&dev))?,
+                Format::Ndjson => {
+                    serde_json::to_writer(&mut *out, &dev.selected(&args.properties))?;
+                    writeln!(out)?;
+                    out.flush()?;
+                }
+                Format::Xml => writeln!(out, "{}", xml_device(&dev))?,
+                Format::Powershell => writeln!(out, "{}", powershell_object(&dev))?,
+                Format::Eventlog => writeln!(out, "{}", logfmt_record(&dev))?,
+            },
+            Err(e) => {
+                out.flush()?;
+                eprintln!("Error occurred: {}", e);
+                return Ok(ExitCode::EnumerationFailed);
+            }
+        }
+    }
+
+    if args.hide_empty {
+        debug!("Hid {} devices without a name or description", hidden);
+    }
+    if (args.unique || args.dedupe_by.is_some()) && !quiet {
+        eprintln!("Removed {} duplicate devices", duplicates);
+    }
+
+    if args.count {
+        writeln!(out, "{}", count)?;
+    } else if args.count_by_class {
+        stats.write_classes(out, "")?;
+    } else if args.summary_only {
+        stats.write(out)?;
+    } else if field_only {
+        // no header or footer to close
+    } else if let Some(previous) = previous {
+        let (added, removed) = snapshot::diff(previous, entries);
+        // between watch runs, say when the changes were seen
+        if args.watch.is_some() && !(added.is_empty() && removed.is_empty()) {
+            writeln!(out, "=== {} ===", utc_timestamp())?;
+        }
+        for (sign, entry) in added
+            .iter()
+            .map(|e| ('+', e))
+            .chain(removed.iter().map(|e| ('-', e)))
+        {
+            let name = entry.name.as_deref().unwrap_or(&args.placeholder);
+            writeln!(out, "{} {} {}", sign, entry.key, name)?;
+        }
+    } else if let Format::Json = args.format {
+        let shown: Vec<Selected> = devs
+            .iter()
+            .map(|dev| dev.selected(&args.properties))
+            .collect();
+        match args.json_pretty {
+            true => serde_json::to_writer_pretty(&mut *out, &shown)?,
+            false => serde_json::to_writer(&mut *out, &shown)?,
+        }
+        writeln!(out)?;
+    } else if let Format::Yaml = args.format {
+        let shown: Vec<Selected> = devs
+            .iter()
+            .map(|dev| dev.selected(&args.properties))
+            .collect();
+        serde_yaml::to_writer(&mut *out, &shown)?;
+    } else if let Format::Xml = args.format {
+        writeln!(out, "{}", XML_FOOTER)?;
+    } else if let Format::Powershell = args.format {
+        writeln!(out, "{}", POWERSHELL_FOOTER)?;
+    } else if let Format::Dot = args.format {
+        write_dot(out, &devs, &text_format, parent_of)?;
+    } else if args.tree && matches!(args.format, Format::Text) {
+        write_tree(out, &devs, &text_format, parent_of)?;
+    } else if let Format::Table = args.format {
+        writeln!(
+            out,
+            "{}",
+            table(&devs, &args.placeholder, args.max_col_width)
+        )?;
+    }
+
+    out.flush()?;
+    if let Some(path) = &args.snapshot {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to write snapshot {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &*entries)?;
+        writer.flush()?;
+    }
+    if args.stats {
+        stats.print()?;
+    }
+    if probing && matched == 0 {
+        return Ok(ExitCode::NotFound);
+    }
+    if args.first_only && matched == 0 {
+        return Ok(ExitCode::NoMatch);
+    }
+    Ok(ExitCode::Success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISK_DRIVE: Guid = Guid(0x4d36e967_e325_11ce_bfc1_08002be10318);
+    const NET: Guid = Guid(0x4d36e972_e325_11ce_bfc1_08002be10318);
+
+    fn device(guid: Guid, name: &str, instance_id: &str, dev_inst: u32) -> WinDev {
+        WinDev::builder()
+            .guid(guid)
+            .fname(name)
+            .class_name(match guid {
+                DISK_DRIVE => "DiskDrive",
+                _ => "Net",
+            })
+            .instance_id(instance_id)
+            .dev_inst(dev_inst)
+            .build()
+    }
+
+    fn devices() -> Vec<WinDev> {
+        vec![
+            device(NET, "Ethernet", "PCI\\VEN_8086", 4),
+            device(DISK_DRIVE, "Samsung SSD", "SCSI\\DISK\\1", 3),
+            device(DISK_DRIVE, "WD Disk", "SCSI\\DISK\\0", 2),
+        ]
+    }
+
+    // What the CLI prints for `flags` over `devs`, with `parents` as the
+    // (child, parent) devnode links.
+    fn render_with(flags: &[&str], devs: Vec<WinDev>, parents: &[(u32, u32)]) -> String {
+        render_source(flags, Box::new(devs), parents)
+    }
+
+    fn render_source(
+        flags: &[&str],
+        source: Box<dyn DeviceSource>,
+        parents: &[(u32, u32)],
+    ) -> String {
+        let args = Cli::parse_from(["print-guid"].iter().chain(flags));
+        let filter = build_filter(&args, Vec::new()).unwrap();
+        let sources = vec![source];
+        let parent_of = |inst: u32| {
+            parents
+                .iter()
+                .find(|&&(child, _)| child == inst)
+                .map(|&(_, parent)| parent)
         };
-        index += 1;
+        let mut out = Vec::new();
+        render(
+            &args,
+            &sources,
+            &filter,
+            &parent_of,
+            &mut out,
+            None,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sorts_by_class_then_instance_id() {
+        let out = render_with(&["--field", "name"], devices(), &[]);
+        assert_eq!(out, "WD Disk\nSamsung SSD\nEthernet\n");
+    }
+
+    #[test]
+    fn no_sort_keeps_the_source_order() {
+        let out = render_with(&["--field", "name", "--no-sort"], devices(), &[]);
+        assert_eq!(out, "Ethernet\nSamsung SSD\nWD Disk\n");
+    }
+
+    #[test]
+    fn limit_zero_prints_nothing() {
+        for format in ["text", "json", "csv", "xml", "powershell", "table"] {
+            let out = render_with(&["--format", format, "--limit", "0"], devices(), &[]);
+            assert_eq!(out, "", "--format {}", format);
+        }
+    }
+
+    // Yields its devices and then fails, like an enumeration that breaks off.
+    struct FailingAfter(Vec<WinDev>);
 
-        let dev = WinDev {
-            fname: get_fname(dev_info_set, &dev_info_data)?,
-            desc: get_desc(dev_info_set, &dev_info_data)?,
-            guid: dev_info_data.ClassGuid.to_u128(),
+    impl DeviceSource for FailingAfter {
+        fn devices(&self) -> Result<Vec<WinDev>> {
+            Err(anyhow!("enumeration failed"))
+        }
+
+        fn iter_devices(&self) -> Box<dyn Iterator<Item = Result<WinDev>> + '_> {
+            let failure = std::iter::once(Err(anyhow!("enumeration failed")));
+            Box::new(self.0.iter().cloned().map(Ok).chain(failure))
+        }
+    }
+
+    #[test]
+    fn limit_and_first_only_stop_the_walk() {
+        for flags in [["--limit", "1"].as_slice(), &["--first-only"]] {
+            let flags = [&["--field", "name"], flags].concat();
+            let out = render_source(&flags, Box::new(FailingAfter(devices())), &[]);
+            assert_eq!(out, "Ethernet\n");
+        }
+    }
+
+    #[test]
+    fn ndjson_streams_in_enumeration_order() {
+        let source = Box::new(FailingAfter(devices()));
+        let out = render_source(&["--format", "ndjson"], source, &[]);
+        let names: Vec<String> = out
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .map(|dev| dev["friendly_name"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(names, ["Ethernet", "Samsung SSD", "WD Disk"]);
+    }
+
+    #[test]
+    fn json_shows_only_the_selected_properties() {
+        let out = render_with(&["--format", "json", "--no-sort"], devices(), &[]);
+        let devs: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(devs[0]["friendly_name"], "Ethernet");
+        assert_eq!(devs[0]["description"], serde_json::Value::Null);
+        assert!(devs[0].get("manufacturer").is_none());
+        assert!(devs[0].get("hardware_ids").is_none());
+    }
+
+    #[test]
+    fn column_formats_fetch_what_they_print() {
+        let fetched = |flags: &[&str]| {
+            let args = Cli::parse_from(["print-guid", "--properties", "mfg"].iter().chain(flags));
+            fetched_properties(&args)
         };
+        let powershell = fetched(&["--format", "powershell"]);
+        assert!(powershell.contains(DeviceProperty::HardwareIds));
+        assert!(powershell.contains(DeviceProperty::FriendlyName));
+        assert!(fetched(&["--format", "csv"]).contains(DeviceProperty::Description));
+        let json = fetched(&["--format", "json"]);
+        assert!(!json.contains(DeviceProperty::HardwareIds));
+        assert!(!json.contains(DeviceProperty::FriendlyName));
+    }
+
+    #[test]
+    fn exclude_guid_repeats_in_either_form() {
+        let args = Cli::parse_from([
+            "print-guid",
+            "--exclude-guid",
+            "{4d36e967-e325-11ce-bfc1-08002be10318}",
+            "--exclude-guid",
+            "0x4d36e972e32511cebfc108002be10318",
+        ]);
+        assert_eq!(args.exclude_guid, [DISK_DRIVE, NET]);
+    }
+
+    #[test]
+    fn probe_conflicts_with_the_class_file() {
+        let disk = DISK_DRIVE.to_string();
+        let args = [
+            "print-guid",
+            "--probe-guid",
+            &disk,
+            "--class-file",
+            "classes.txt",
+        ];
+        let e = Cli::try_parse_from(args).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn machine_conflicts_with_the_tree() {
+        let args = ["print-guid", "--machine", "host", "--tree"];
+        let e = Cli::try_parse_from(args).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn filters_synthetic_devices() {
+        let disk = DISK_DRIVE.to_string();
+        let out = render_with(
+            &["--field", "name", "--exclude-guid", &disk],
+            devices(),
+            &[],
+        );
+        assert_eq!(out, "Ethernet\n");
+        let out = render_with(
+            &["--field", "name", "--name-contains", "ssd"],
+            devices(),
+            &[],
+        );
+        assert_eq!(out, "Samsung SSD\n");
+    }
+
+    #[test]
+    fn tree_skips_devnodes_that_are_not_listed() {
+        // 1 is an unlisted root hub, the SSD hangs off the WD disk's devnode
+        let parents = [(2, 1), (3, 2), (4, 1)];
+        let out = render_with(&["--tree", "--color", "never"], devices(), &parents);
+        assert_eq!(
+            out,
+            "WD Disk [DiskDrive]\n  Samsung SSD [DiskDrive]\nEthernet [Net]\n"
+        );
+    }
 
-        println!("{}", dev);
+    #[test]
+    fn dedupe_by_keeps_the_first_device_per_key() {
+        let flags = |field| ["--field", "name", "--no-sort", "--dedupe-by", field];
+        let out = render_with(&flags("guid"), devices(), &[]);
+        assert_eq!(out, "Ethernet\nSamsung SSD\n");
+        let out = render_with(&flags("class"), devices(), &[]);
+        assert_eq!(out, "Ethernet\nSamsung SSD\n");
+
+        let mut devs = devices();
+        devs.push(device(DISK_DRIVE, "Copy", "SCSI\\DISK\\1", 5));
+        let out = render_with(&flags("instance"), devs, &[]);
+        assert_eq!(out, "Ethernet\nSamsung SSD\nWD Disk\n");
+    }
+
+    #[test]
+    fn dedupe_by_keeps_every_device_without_the_key() {
+        let unkeyed = |name: &str| WinDev::builder().guid(NET).fname(name).build();
+        let devs = vec![unkeyed("First"), unkeyed("Second")];
+        let flags = |field| ["--field", "name", "--no-sort", "--dedupe-by", field];
+        for field in ["instance", "class"] {
+            let out = render_with(&flags(field), devs.clone(), &[]);
+            assert_eq!(out, "First\nSecond\n");
+        }
     }
 }
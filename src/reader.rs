@@ -0,0 +1,200 @@
+use crate::{DeviceProperty, Guid, PropertySelection, WinDev};
+use anyhow::Result;
+use log::{debug, warn};
+
+/// The raw reads behind one device's properties: SetupAPI when enumerating,
+/// canned values in the tests.
+pub(crate) trait PropertyGetter {
+    /// A REG_SZ property, None when the device doesn't have it.
+    fn string(&self, property: DeviceProperty) -> Result<Option<String>>;
+    /// A REG_MULTI_SZ property, empty when the device doesn't have it.
+    fn strings(&self, property: DeviceProperty) -> Result<Vec<String>>;
+    /// A property stored as a raw 16 byte GUID.
+    fn guid(&self, property: DeviceProperty) -> Result<Option<Guid>>;
+    /// A REG_DWORD property.
+    fn dword(&self, property: DeviceProperty) -> Result<Option<u32>>;
+    fn instance_id(&self) -> Result<String>;
+}
+
+// Reads the properties of one device, skipping the unselected ones. Errors
+// either abort the device or are logged and kept as diagnostics.
+pub(crate) struct PropertyReader<'a> {
+    pub properties: &'a PropertySelection,
+    pub fail_fast: bool,
+    pub lenient: bool,
+    pub diagnostics: Vec<String>,
+}
+
+impl PropertyReader<'_> {
+    pub fn read<T: Default>(
+        &mut self,
+        property: DeviceProperty,
+        get: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        match self.properties.contains(property) {
+            true => self.always(property.name(), get),
+            false => Ok(T::default()),
+        }
+    }
+
+    // for values read regardless of the property selection
+    pub fn always<T: Default>(&mut self, what: &str, get: impl FnOnce() -> Result<T>) -> Result<T> {
+        match get() {
+            Ok(value) => Ok(value),
+            Err(e) if self.lenient => {
+                debug!("Ignoring failed read of {}: {}", what, e);
+                Ok(T::default())
+            }
+            Err(e) if self.fail_fast => Err(e),
+            Err(e) => {
+                warn!("Failed to read {}: {}", what, e);
+                self.diagnostics.push(format!("{}: {}", what, e));
+                Ok(T::default())
+            }
+        }
+    }
+}
+
+// Reads the device's selected properties along with its instance ID. The
+// class GUID, class name, interfaces and diagnostics are left to the caller.
+pub(crate) fn read_properties(
+    reader: &mut PropertyReader,
+    device: &impl PropertyGetter,
+) -> Result<WinDev> {
+    use DeviceProperty::*;
+    Ok(WinDev {
+        fname: reader.read(FriendlyName, || device.string(FriendlyName))?,
+        desc: reader.read(Description, || device.string(Description))?,
+        class_string: reader.read(ClassString, || device.string(ClassString))?,
+        hardware_ids: reader.read(HardwareIds, || device.strings(HardwareIds))?,
+        compatible_ids: reader.read(CompatibleIds, || device.strings(CompatibleIds))?,
+        manufacturer: reader.read(Manufacturer, || device.string(Manufacturer))?,
+        instance_id: reader.always("instance ID", || device.instance_id())?,
+        service: reader.read(Service, || device.string(Service))?,
+        enumerator: reader.read(Enumerator, || device.string(Enumerator))?,
+        bus_type: reader.read(BusType, || device.guid(BusType))?,
+        location: reader.read(Location, || device.string(Location))?,
+        driver_key: reader.read(DriverKey, || device.string(DriverKey))?,
+        config_flags: reader.read(ConfigFlags, || device.dword(ConfigFlags))?,
+        capabilities: reader.read(Capabilities, || device.dword(Capabilities))?,
+        ..WinDev::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::cell::RefCell;
+
+    // Answers every read with a fixed value and remembers what was asked.
+    // Reads of `failing` fail the way a denied SetupAPI call does.
+    #[derive(Default)]
+    struct FakeDevice {
+        failing: Option<DeviceProperty>,
+        queried: RefCell<Vec<DeviceProperty>>,
+    }
+
+    impl FakeDevice {
+        fn query(&self, property: DeviceProperty) -> Result<()> {
+            self.queried.borrow_mut().push(property);
+            match self.failing == Some(property) {
+                true => Err(anyhow!("access denied")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    impl PropertyGetter for FakeDevice {
+        fn string(&self, property: DeviceProperty) -> Result<Option<String>> {
+            self.query(property)?;
+            Ok(Some(property.name().to_string()))
+        }
+
+        fn strings(&self, property: DeviceProperty) -> Result<Vec<String>> {
+            self.query(property)?;
+            Ok(vec![property.name().to_string()])
+        }
+
+        fn guid(&self, property: DeviceProperty) -> Result<Option<Guid>> {
+            self.query(property)?;
+            Ok(Some(Guid(1)))
+        }
+
+        fn dword(&self, property: DeviceProperty) -> Result<Option<u32>> {
+            self.query(property)?;
+            Ok(Some(1))
+        }
+
+        fn instance_id(&self) -> Result<String> {
+            Ok("ROOT\\FAKE\\0000".to_string())
+        }
+    }
+
+    fn reader(properties: &PropertySelection) -> PropertyReader<'_> {
+        PropertyReader {
+            properties,
+            fail_fast: false,
+            lenient: false,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn reads_only_the_selected_properties() {
+        let props: PropertySelection = "desc,hwids".parse().unwrap();
+        let device = FakeDevice::default();
+        let dev = read_properties(&mut reader(&props), &device).unwrap();
+        assert_eq!(
+            *device.queried.borrow(),
+            [DeviceProperty::Description, DeviceProperty::HardwareIds]
+        );
+        assert_eq!(dev.desc.as_deref(), Some("desc"));
+        assert_eq!(dev.hardware_ids, ["hwids"]);
+        assert_eq!(dev.instance_id, "ROOT\\FAKE\\0000");
+        assert!(dev.fname.is_none());
+        assert!(dev.bus_type.is_none());
+        assert!(dev.config_flags.is_none());
+    }
+
+    #[test]
+    fn a_failed_read_becomes_a_diagnostic() {
+        let props = PropertySelection::all();
+        let device = FakeDevice {
+            failing: Some(DeviceProperty::Service),
+            ..FakeDevice::default()
+        };
+        let mut reader = reader(&props);
+        let dev = read_properties(&mut reader, &device).unwrap();
+        assert!(dev.service.is_none());
+        assert_eq!(dev.manufacturer.as_deref(), Some("mfg"));
+        assert_eq!(reader.diagnostics, ["service: access denied"]);
+
+        reader.fail_fast = true;
+        assert!(read_properties(&mut reader, &device).is_err());
+        // an unselected property can't fail the device
+        let props: PropertySelection = "name".parse().unwrap();
+        let mut reader = PropertyReader {
+            fail_fast: true,
+            ..self::reader(&props)
+        };
+        assert!(read_properties(&mut reader, &device).is_ok());
+    }
+
+    #[test]
+    fn failed_reads_follow_the_context() {
+        let props = PropertySelection::all();
+        let denied = || -> Result<Option<String>> { Err(anyhow!("access denied")) };
+        let mut reader = reader(&props);
+        assert_eq!(reader.read(DeviceProperty::Service, denied).unwrap(), None);
+        assert_eq!(reader.diagnostics, ["service: access denied"]);
+
+        reader.fail_fast = true;
+        assert!(reader.read(DeviceProperty::Service, denied).is_err());
+
+        // lenient wins over fail_fast and keeps no diagnostic
+        reader.lenient = true;
+        assert_eq!(reader.read(DeviceProperty::Service, denied).unwrap(), None);
+        assert_eq!(reader.diagnostics.len(), 1);
+    }
+}
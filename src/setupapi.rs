@@ -0,0 +1,777 @@
+use crate::reader::{read_properties, PropertyGetter, PropertyReader};
+use crate::{
+    decode_utf16, decode_utf16_sized, split_multi_sz, trim_property_buffer, DeviceInterface,
+    DeviceProperty, DeviceSource, Guid, PropertySelection, WinDev,
+};
+use anyhow::{anyhow, Result};
+use log::debug;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+use windows::core::{GUID, HSTRING};
+use windows::Win32::Devices::DeviceAndDriverInstallation::{
+    CM_Get_Child, CM_Get_Parent, CM_Get_Sibling, SetupDiClassGuidsFromNameW,
+    SetupDiClassNameFromGuidW, SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInfo,
+    SetupDiEnumDeviceInterfaces, SetupDiGetClassDevsExW, SetupDiGetClassDevsW,
+    SetupDiGetDeviceInstanceIdW, SetupDiGetDeviceInterfaceDetailW,
+    SetupDiGetDeviceRegistryPropertyW, CR_SUCCESS, DIGCF_ALLCLASSES, DIGCF_PRESENT, HDEVINFO,
+    MAX_CLASS_NAME_LEN, SETUP_DI_GET_CLASS_DEVS_FLAGS, SETUP_DI_REGISTRY_PROPERTY,
+    SPDRP_BUSTYPEGUID, SPDRP_CAPABILITIES, SPDRP_CLASS, SPDRP_COMPATIBLEIDS, SPDRP_CONFIGFLAGS,
+    SPDRP_DEVICEDESC, SPDRP_DRIVER, SPDRP_ENUMERATOR_NAME, SPDRP_FRIENDLYNAME, SPDRP_HARDWAREID,
+    SPDRP_LOCATION_INFORMATION, SPDRP_MFG, SPDRP_SERVICE, SP_DEVICE_INTERFACE_DATA,
+    SP_DEVICE_INTERFACE_DETAIL_DATA_W, SP_DEVINFO_DATA,
+};
+use windows::Win32::Foundation::{
+    ERROR_ACCESS_DENIED, ERROR_INSUFFICIENT_BUFFER, ERROR_INVALID_CLASS, ERROR_INVALID_DATA,
+    ERROR_NO_MORE_ITEMS,
+};
+
+// Fetches the raw bytes of a registry property, sizing the buffer with a
+// first probing call. Returns None when the device lacks the property.
+fn get_property_buffer(
+    dev_info_set: HDEVINFO,
+    dev_info_data: *const SP_DEVINFO_DATA,
+    property: SETUP_DI_REGISTRY_PROPERTY,
+) -> Result<Option<Vec<u8>>> {
+    let mut required_size: u32 = 0;
+
+    unsafe {
+        if let Err(e) = SetupDiGetDeviceRegistryPropertyW(
+            dev_info_set,
+            dev_info_data,
+            property,
+            None,
+            None,
+            Some(&mut required_size),
+        ) {
+            match e.code() {
+                c if c == ERROR_INSUFFICIENT_BUFFER.to_hresult() => {}
+                // the property does not exist for this device
+                c if c == ERROR_INVALID_DATA.to_hresult() => {
+                    debug!("Property {} not present", property.0);
+                    return Ok(None);
+                }
+                // not readable without elevation, leave the field empty
+                c if c == ERROR_ACCESS_DENIED.to_hresult() => {
+                    debug!("Access denied reading property {}", property.0);
+                    return Ok(None);
+                }
+                _ => return Err(e.into()),
+            }
+        }
+    }
+
+    let mut buffer: Vec<u8> = vec![0; required_size as usize];
+    unsafe {
+        SetupDiGetDeviceRegistryPropertyW(
+            dev_info_set,
+            dev_info_data,
+            property,
+            None,
+            Some(&mut buffer),
+            Some(&mut required_size),
+        )?;
+    }
+
+    // the second call reports how much it actually wrote
+    if required_size == 0 {
+        debug!(
+            "Property {} reported no size, scanning for a null",
+            property.0
+        );
+    }
+    Ok(Some(trim_property_buffer(buffer, required_size as usize)))
+}
+
+impl DeviceProperty {
+    /// The `SPDRP_*` constant SetupAPI knows this property by.
+    pub fn spdrp(self) -> SETUP_DI_REGISTRY_PROPERTY {
+        match self {
+            DeviceProperty::FriendlyName => SPDRP_FRIENDLYNAME,
+            DeviceProperty::Description => SPDRP_DEVICEDESC,
+            DeviceProperty::Manufacturer => SPDRP_MFG,
+            DeviceProperty::Service => SPDRP_SERVICE,
+            DeviceProperty::Location => SPDRP_LOCATION_INFORMATION,
+            DeviceProperty::DriverKey => SPDRP_DRIVER,
+            DeviceProperty::HardwareIds => SPDRP_HARDWAREID,
+            DeviceProperty::ClassString => SPDRP_CLASS,
+            DeviceProperty::Enumerator => SPDRP_ENUMERATOR_NAME,
+            DeviceProperty::BusType => SPDRP_BUSTYPEGUID,
+            DeviceProperty::CompatibleIds => SPDRP_COMPATIBLEIDS,
+            DeviceProperty::ConfigFlags => SPDRP_CONFIGFLAGS,
+            DeviceProperty::Capabilities => SPDRP_CAPABILITIES,
+        }
+    }
+}
+
+// Reads a REG_SZ property, None when the device doesn't have it.
+fn get_string_property(
+    dev_info_set: HDEVINFO,
+    dev_info_data: *const SP_DEVINFO_DATA,
+    property: SETUP_DI_REGISTRY_PROPERTY,
+) -> Result<Option<String>> {
+    let buffer = get_property_buffer(dev_info_set, dev_info_data, property)?;
+    Ok(buffer.map(|b| decode_utf16_sized(&b)))
+}
+
+// Reads a property stored as a raw 16 byte GUID rather than a string, such
+// as the bus type.
+fn get_guid_property(
+    dev_info_set: HDEVINFO,
+    dev_info_data: *const SP_DEVINFO_DATA,
+    property: SETUP_DI_REGISTRY_PROPERTY,
+) -> Result<Option<Guid>> {
+    let Some(buffer) = get_property_buffer(dev_info_set, dev_info_data, property)? else {
+        return Ok(None);
+    };
+    let bytes: [u8; 16] = buffer
+        .as_slice()
+        .try_into()
+        .map_err(|_| anyhow!("GUID property has {} bytes, expected 16", buffer.len()))?;
+    let guid = GUID::from_values(
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+        u16::from_le_bytes([bytes[6], bytes[7]]),
+        [
+            bytes[8], bytes[9], bytes[10], bytes[11], bytes[12], bytes[13], bytes[14], bytes[15],
+        ],
+    );
+    Ok(Some(guid.into()))
+}
+
+// Reads a REG_DWORD property, None when the device lacks it.
+fn get_dword_property(
+    dev_info_set: HDEVINFO,
+    dev_info_data: *const SP_DEVINFO_DATA,
+    property: SETUP_DI_REGISTRY_PROPERTY,
+) -> Result<Option<u32>> {
+    let Some(buffer) = get_property_buffer(dev_info_set, dev_info_data, property)? else {
+        return Ok(None);
+    };
+    let bytes: [u8; 4] = buffer
+        .as_slice()
+        .try_into()
+        .map_err(|_| anyhow!("DWORD property has {} bytes, expected 4", buffer.len()))?;
+    Ok(Some(u32::from_le_bytes(bytes)))
+}
+
+// Reads a REG_MULTI_SZ property into its strings, empty when the device
+// lacks it.
+fn read_multi_string_property(
+    dev_info_set: HDEVINFO,
+    dev_info_data: *const SP_DEVINFO_DATA,
+    property: SETUP_DI_REGISTRY_PROPERTY,
+) -> Result<Vec<String>> {
+    let buffer = get_property_buffer(dev_info_set, dev_info_data, property)?;
+    Ok(buffer.map(|b| split_multi_sz(&b)).unwrap_or_default())
+}
+
+fn get_instance_id(
+    dev_info_set: HDEVINFO,
+    dev_info_data: *const SP_DEVINFO_DATA,
+) -> Result<String> {
+    let mut required_size: u32 = 0;
+
+    unsafe {
+        // first call only reports the needed length (in characters)
+        if let Err(e) =
+            SetupDiGetDeviceInstanceIdW(dev_info_set, dev_info_data, None, Some(&mut required_size))
+        {
+            if e.code() != ERROR_INSUFFICIENT_BUFFER.to_hresult() {
+                return Err(e.into());
+            }
+        }
+    }
+
+    let mut buffer: Vec<u16> = vec![0; required_size as usize];
+    unsafe {
+        SetupDiGetDeviceInstanceIdW(
+            dev_info_set,
+            dev_info_data,
+            Some(&mut buffer),
+            Some(&mut required_size),
+        )?;
+    }
+
+    let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+    Ok(String::from_utf16_lossy(&buffer[..len]))
+}
+
+// Lists the interfaces of one interface class that a device exposes.
+fn get_interfaces(
+    dev_info_set: HDEVINFO,
+    dev_info_data: *const SP_DEVINFO_DATA,
+    interface_class: &GUID,
+) -> Result<Vec<DeviceInterface>> {
+    let mut interfaces = Vec::new();
+    for index in 0.. {
+        let mut interface_data = SP_DEVICE_INTERFACE_DATA {
+            cbSize: std::mem::size_of::<SP_DEVICE_INTERFACE_DATA>() as u32,
+            ..Default::default()
+        };
+        if let Err(e) = unsafe {
+            SetupDiEnumDeviceInterfaces(
+                dev_info_set,
+                Some(dev_info_data),
+                interface_class,
+                index,
+                &mut interface_data,
+            )
+        } {
+            if e.code() == ERROR_NO_MORE_ITEMS.to_hresult() {
+                break;
+            }
+            return Err(e.into());
+        }
+        interfaces.push(DeviceInterface {
+            interface_guid: interface_data.InterfaceClassGuid.into(),
+            path: get_interface_path(dev_info_set, &interface_data)?,
+        });
+    }
+    Ok(interfaces)
+}
+
+// Reads the symbolic link path of an interface. The detail struct is variable
+// length, so the first call only reports the size it needs.
+fn get_interface_path(
+    dev_info_set: HDEVINFO,
+    interface_data: &SP_DEVICE_INTERFACE_DATA,
+) -> Result<String> {
+    let mut required_size: u32 = 0;
+    if let Err(e) = unsafe {
+        SetupDiGetDeviceInterfaceDetailW(
+            dev_info_set,
+            interface_data,
+            None,
+            0,
+            Some(&mut required_size),
+            None,
+        )
+    } {
+        if e.code() != ERROR_INSUFFICIENT_BUFFER.to_hresult() {
+            return Err(e.into());
+        }
+    }
+
+    let header = std::mem::size_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>();
+    let path_start = std::mem::offset_of!(SP_DEVICE_INTERFACE_DETAIL_DATA_W, DevicePath);
+    let size = required_size as usize;
+    if size < path_start {
+        return Err(anyhow!(
+            "Interface detail size {} is smaller than its header",
+            size
+        ));
+    }
+
+    // u32 words keep the buffer aligned for the cbSize header, and it is
+    // never smaller than the struct cbSize is written into
+    let mut buffer = vec![0u32; size.max(header).div_ceil(4)];
+    let detail = buffer.as_mut_ptr() as *mut SP_DEVICE_INTERFACE_DETAIL_DATA_W;
+    unsafe {
+        // cbSize is the fixed part of the struct, not the whole buffer
+        (*detail).cbSize = header as u32;
+        SetupDiGetDeviceInterfaceDetailW(
+            dev_info_set,
+            interface_data,
+            Some(detail),
+            (buffer.len() * 4) as u32,
+            None,
+            None,
+        )?;
+    }
+
+    let bytes: Vec<u8> = buffer.iter().flat_map(|w| w.to_le_bytes()).collect();
+    Ok(decode_utf16(&bytes[path_start..size]))
+}
+
+/// The devnode a device hangs off of (e.g. its USB hub), if any.
+pub fn parent_dev_inst(dev_inst: u32) -> Option<u32> {
+    let mut parent = 0;
+    match unsafe { CM_Get_Parent(&mut parent, dev_inst, 0) } {
+        CR_SUCCESS => Some(parent),
+        _ => None,
+    }
+}
+
+/// The devnodes directly below a device, in Config Manager order.
+pub fn child_dev_insts(dev_inst: u32) -> Vec<u32> {
+    let mut children = Vec::new();
+    let mut child = 0;
+    if unsafe { CM_Get_Child(&mut child, dev_inst, 0) } != CR_SUCCESS {
+        return children;
+    }
+    children.push(child);
+    let mut sibling = 0;
+    while unsafe { CM_Get_Sibling(&mut sibling, child, 0) } == CR_SUCCESS {
+        children.push(sibling);
+        child = sibling;
+    }
+    children
+}
+
+/// Looks up the installer class name (e.g. "DiskDrive", "Net") of a class GUID.
+pub fn get_class_name(guid: &GUID) -> Result<Option<String>> {
+    let mut buffer: Vec<u16> = vec![0; MAX_CLASS_NAME_LEN as usize];
+    let mut required_size: u32 = 0;
+
+    unsafe {
+        if let Err(e) = SetupDiClassNameFromGuidW(guid, &mut buffer, Some(&mut required_size)) {
+            // no class registered under this GUID
+            if e.code() == ERROR_INVALID_CLASS.to_hresult() {
+                debug!("No class name registered for {:?}", guid);
+                return Ok(None);
+            }
+            return Err(e.into());
+        }
+    }
+
+    let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+    Ok(Some(String::from_utf16_lossy(&buffer[..len])))
+}
+
+/// Resolves an installer class name to every class GUID registered under it.
+pub fn class_guids_from_name(name: &str) -> Result<Vec<GUID>> {
+    let name = HSTRING::from(name);
+    let mut required_size: u32 = 0;
+
+    unsafe {
+        if let Err(e) = SetupDiClassGuidsFromNameW(&name, &mut [], &mut required_size) {
+            if e.code() != ERROR_INSUFFICIENT_BUFFER.to_hresult() {
+                return Err(e.into());
+            }
+        }
+    }
+
+    let mut guids = vec![GUID::zeroed(); required_size as usize];
+    if !guids.is_empty() {
+        unsafe { SetupDiClassGuidsFromNameW(&name, &mut guids, &mut required_size) }?;
+    }
+    guids.truncate(required_size as usize);
+    Ok(guids)
+}
+
+/// Owns an `HDEVINFO` and destroys it with `SetupDiDestroyDeviceInfoList`
+/// when dropped.
+pub struct DeviceInfoSet(HDEVINFO);
+
+impl DeviceInfoSet {
+    /// Opens the device set, limited to one setup class if given. With
+    /// `present_only` unset, installed but disconnected devices are included.
+    pub fn open(class_guid: Option<&GUID>, present_only: bool) -> Result<Self> {
+        Self::open_on(class_guid, present_only, None)
+    }
+
+    /// Like `open`, but reads the devices of another machine when `machine`
+    /// is given, via `SetupDiGetClassDevsExW`. That needs administrator
+    /// rights there and its Remote Registry service running, and recent
+    /// Windows versions refuse remote device access altogether.
+    pub fn open_on(
+        class_guid: Option<&GUID>,
+        present_only: bool,
+        machine: Option<&str>,
+    ) -> Result<Self> {
+        let mut flags = SETUP_DI_GET_CLASS_DEVS_FLAGS(0);
+        if present_only {
+            flags |= DIGCF_PRESENT;
+        }
+        if class_guid.is_none() {
+            flags |= DIGCF_ALLCLASSES;
+        }
+        let class_guid_ptr = class_guid.map(|guid| guid as *const GUID);
+
+        let dev_info_set = match machine {
+            None => unsafe { SetupDiGetClassDevsW(class_guid_ptr, None, None, flags) }?,
+            Some(machine) => {
+                // the Ex call wants a UNC name
+                let unc = match machine.starts_with(r"\\") {
+                    true => machine.to_string(),
+                    false => format!(r"\\{}", machine),
+                };
+                unsafe {
+                    SetupDiGetClassDevsExW(
+                        class_guid_ptr,
+                        None,
+                        None,
+                        flags,
+                        None,
+                        &HSTRING::from(unc.as_str()),
+                        None,
+                    )
+                }
+                .map_err(|e| anyhow!("Failed to connect to {}: {}", unc, e))?
+            }
+        };
+
+        if dev_info_set.is_invalid() {
+            return Err(anyhow!("Failed to get device list"));
+        }
+        debug!(
+            "Opened device info set (class: {:?}, flags: {:#x}, present only: {}, machine: {:?})",
+            class_guid, flags.0, present_only, machine
+        );
+
+        Ok(Self(dev_info_set))
+    }
+
+    /// The raw handle, valid for as long as `self` is alive.
+    pub fn handle(&self) -> HDEVINFO {
+        self.0
+    }
+}
+
+impl Drop for DeviceInfoSet {
+    fn drop(&mut self) {
+        // nothing sensible to do if releasing the list fails
+        let _ = unsafe { SetupDiDestroyDeviceInfoList(self.0) };
+    }
+}
+
+/// Runs `open` (typically `DeviceEnumerator::open`) up to `retries` more
+/// times while it fails, waiting a little longer before each attempt, and
+/// returns the last error if none succeed. Device initialization can make
+/// `SetupDiGetClassDevs` fail briefly.
+pub fn with_retries<T>(retries: u32, mut open: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 0;
+    loop {
+        match open() {
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                let delay = Duration::from_millis(100 * u64::from(attempt));
+                debug!(
+                    "Attempt {} failed ({}), retrying in {:?}",
+                    attempt, e, delay
+                );
+                thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// How `WinDev::from_device_with` reads a device, along with the class names it
+/// has looked up so far. Reuse one across the devices of a walk.
+#[derive(Default)]
+pub struct ReadContext {
+    /// Make a failed property read an error for the whole device instead of
+    /// an entry in `WinDev::diagnostics`.
+    pub fail_fast: bool,
+    /// Treat every failed property read as a missing property, logged at
+    /// debug level only. Overrides `fail_fast`.
+    pub lenient: bool,
+    /// Also collect the device's interfaces of this interface class.
+    pub interface_class: Option<GUID>,
+    // many devices share a class, so each name is looked up once
+    class_names: RefCell<HashMap<u128, Option<String>>>,
+}
+
+impl ReadContext {
+    fn class_name(&self, guid: &GUID) -> Result<Option<String>> {
+        if let Some(name) = self.class_names.borrow().get(&guid.to_u128()) {
+            return Ok(name.clone());
+        }
+        let name = get_class_name(guid)?;
+        self.class_names
+            .borrow_mut()
+            .insert(guid.to_u128(), name.clone());
+        Ok(name)
+    }
+}
+
+/// Enumerates devices, either of every setup class or of a single one.
+pub struct DeviceEnumerator {
+    dev_info_set: DeviceInfoSet,
+    properties: PropertySelection,
+    context: ReadContext,
+}
+
+impl DeviceEnumerator {
+    pub fn new(present_only: bool) -> Result<Self> {
+        Self::open(None, present_only, None)
+    }
+
+    /// Enumerates only the devices of one setup class, letting SetupAPI do
+    /// the filtering instead of walking every device.
+    pub fn for_class(class_guid: &GUID, present_only: bool) -> Result<Self> {
+        Self::open(Some(class_guid), present_only, None)
+    }
+
+    /// The general form of `new` and `for_class`, optionally reading another
+    /// machine's devices (see `DeviceInfoSet::open_on`).
+    pub fn open(
+        class_guid: Option<&GUID>,
+        present_only: bool,
+        machine: Option<&str>,
+    ) -> Result<Self> {
+        Ok(Self {
+            dev_info_set: DeviceInfoSet::open_on(class_guid, present_only, machine)?,
+            properties: PropertySelection::all(),
+            context: ReadContext::default(),
+        })
+    }
+
+    /// Restricts which properties are read for each device; the rest are
+    /// left empty without touching SetupAPI. Defaults to all of them.
+    pub fn with_properties(mut self, properties: PropertySelection) -> Self {
+        self.properties = properties;
+        self
+    }
+
+    /// Makes a failed property read an error for the whole device instead of
+    /// an entry in `WinDev::diagnostics`.
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.context.fail_fast = fail_fast;
+        self
+    }
+
+    /// Treats every failed property read as a missing property, logged at
+    /// debug level only. Meant for partial SetupAPI implementations such as
+    /// Wine's, where property calls can fail with unexpected error codes;
+    /// support for those is best-effort. Overrides `with_fail_fast`.
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.context.lenient = lenient;
+        self
+    }
+
+    /// Also collects each device's interfaces of the given interface class
+    /// (e.g. `GUID_DEVINTERFACE_USB_DEVICE`) into `WinDev::interfaces`.
+    pub fn with_interfaces(mut self, interface_class: GUID) -> Self {
+        self.context.interface_class = Some(interface_class);
+        self
+    }
+
+    /// Walks the device info set, yielding one `WinDev` per device.
+    pub fn iter(&self) -> impl Iterator<Item = Result<WinDev>> + '_ {
+        let mut cursor = Cursor::default();
+        std::iter::from_fn(move || cursor.next(self))
+    }
+}
+
+impl DeviceSource for DeviceEnumerator {
+    fn devices(&self) -> Result<Vec<WinDev>> {
+        self.iter().collect()
+    }
+
+    fn iter_devices(&self) -> Box<dyn Iterator<Item = Result<WinDev>> + '_> {
+        Box::new(self.iter())
+    }
+}
+
+// Walk position shared by `DeviceEnumerator::iter` and `DeviceListIter`.
+#[derive(Default)]
+struct Cursor {
+    index: u32,
+    done: bool,
+}
+
+impl Cursor {
+    fn next(&mut self, enumerator: &DeviceEnumerator) -> Option<Result<WinDev>> {
+        if self.done {
+            return None;
+        }
+
+        let mut dev_info_data = SP_DEVINFO_DATA {
+            cbSize: std::mem::size_of::<SP_DEVINFO_DATA>() as u32,
+            ..Default::default()
+        };
+        if let Err(e) = unsafe {
+            SetupDiEnumDeviceInfo(
+                enumerator.dev_info_set.handle(),
+                self.index,
+                &mut dev_info_data,
+            )
+        } {
+            self.done = true;
+            return end_of_enumeration(e, self.index).map(Err);
+        };
+        self.index += 1;
+
+        Some(WinDev::from_device_with(
+            enumerator.dev_info_set.handle(),
+            &dev_info_data,
+            &enumerator.properties,
+            &enumerator.context,
+        ))
+    }
+}
+
+// What a failed SetupDiEnumDeviceInfo at `index` means for the walk: None
+// when the devices ran out, the error to report otherwise.
+fn end_of_enumeration(e: windows::core::Error, index: u32) -> Option<anyhow::Error> {
+    // Exit code for no more devices
+    match e.code() == ERROR_NO_MORE_ITEMS.to_hresult() {
+        true => {
+            debug!("Enumeration finished after {} devices", index);
+            None
+        }
+        false => {
+            debug!("Enumeration failed at index {}: {}", index, e);
+            Some(e.into())
+        }
+    }
+}
+
+/// An owned device list for `for` loops; the device info set is released
+/// when the loop's iterator is dropped.
+pub struct DeviceList(DeviceEnumerator);
+
+impl DeviceList {
+    /// Devices that are currently connected.
+    pub fn present() -> Result<Self> {
+        DeviceEnumerator::new(true).map(Self)
+    }
+
+    /// Every installed device, connected or not.
+    pub fn all() -> Result<Self> {
+        DeviceEnumerator::new(false).map(Self)
+    }
+}
+
+impl From<DeviceEnumerator> for DeviceList {
+    fn from(enumerator: DeviceEnumerator) -> Self {
+        Self(enumerator)
+    }
+}
+
+impl IntoIterator for DeviceList {
+    type Item = Result<WinDev>;
+    type IntoIter = DeviceListIter;
+
+    fn into_iter(self) -> DeviceListIter {
+        DeviceListIter {
+            enumerator: self.0,
+            cursor: Cursor::default(),
+        }
+    }
+}
+
+/// Iterator returned by `DeviceList::into_iter`, reading one device per
+/// `next` call.
+pub struct DeviceListIter {
+    enumerator: DeviceEnumerator,
+    cursor: Cursor,
+}
+
+impl Iterator for DeviceListIter {
+    type Item = Result<WinDev>;
+
+    fn next(&mut self) -> Option<Result<WinDev>> {
+        self.cursor.next(&self.enumerator)
+    }
+}
+
+// One device of a device info set, read through SetupAPI.
+struct SetupApiDevice<'a> {
+    dev_info_set: HDEVINFO,
+    data: &'a SP_DEVINFO_DATA,
+}
+
+impl PropertyGetter for SetupApiDevice<'_> {
+    fn string(&self, property: DeviceProperty) -> Result<Option<String>> {
+        get_string_property(self.dev_info_set, self.data, property.spdrp())
+    }
+
+    fn strings(&self, property: DeviceProperty) -> Result<Vec<String>> {
+        read_multi_string_property(self.dev_info_set, self.data, property.spdrp())
+    }
+
+    fn guid(&self, property: DeviceProperty) -> Result<Option<Guid>> {
+        get_guid_property(self.dev_info_set, self.data, property.spdrp())
+    }
+
+    fn dword(&self, property: DeviceProperty) -> Result<Option<u32>> {
+        get_dword_property(self.dev_info_set, self.data, property.spdrp())
+    }
+
+    fn instance_id(&self) -> Result<String> {
+        get_instance_id(self.dev_info_set, self.data)
+    }
+}
+
+impl WinDev {
+    /// Reads one device of a device info set, fetching only the properties in
+    /// `props`. Failed reads end up in `diagnostics`.
+    pub fn from_device(
+        dev_info_set: HDEVINFO,
+        data: &SP_DEVINFO_DATA,
+        props: &PropertySelection,
+    ) -> Result<WinDev> {
+        Self::from_device_with(dev_info_set, data, props, &ReadContext::default())
+    }
+
+    /// `from_device` with a `context` that decides what happens to failed
+    /// reads and caches class names between calls.
+    pub fn from_device_with(
+        dev_info_set: HDEVINFO,
+        data: &SP_DEVINFO_DATA,
+        props: &PropertySelection,
+        context: &ReadContext,
+    ) -> Result<WinDev> {
+        let mut reader = PropertyReader {
+            properties: props,
+            fail_fast: context.fail_fast,
+            lenient: context.lenient,
+            diagnostics: Vec::new(),
+        };
+        let device = SetupApiDevice { dev_info_set, data };
+        let mut dev = read_properties(&mut reader, &device)?;
+        dev.guid = data.ClassGuid.into();
+        dev.dev_inst = data.DevInst;
+        dev.class_name = reader.always("class name", || context.class_name(&data.ClassGuid))?;
+        if let Some(class) = &context.interface_class {
+            dev.interfaces =
+                reader.always("interfaces", || get_interfaces(dev_info_set, data, class))?;
+        }
+        dev.diagnostics = reader.diagnostics;
+        Ok(dev)
+    }
+}
+
+/// Collects every device into a vector, releasing the device info set once
+/// the walk is done.
+pub fn enumerate_devices(present_only: bool) -> Result<Vec<WinDev>> {
+    DeviceEnumerator::new(present_only)?.devices()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_info_set_is_released_on_drop() {
+        for present_only in [true, false] {
+            let set = DeviceInfoSet::open(None, present_only).unwrap();
+            drop(set);
+        }
+    }
+
+    #[test]
+    fn no_more_items_ends_the_walk() {
+        let done = windows::core::Error::from_hresult(ERROR_NO_MORE_ITEMS.to_hresult());
+        assert!(end_of_enumeration(done, 7).is_none());
+        let denied = windows::core::Error::from_hresult(ERROR_ACCESS_DENIED.to_hresult());
+        assert!(end_of_enumeration(denied, 7).is_some());
+    }
+
+    #[test]
+    fn from_device_reads_only_the_selected_properties() {
+        let set = DeviceInfoSet::open(None, true).unwrap();
+        let mut data = SP_DEVINFO_DATA {
+            cbSize: std::mem::size_of::<SP_DEVINFO_DATA>() as u32,
+            ..Default::default()
+        };
+        unsafe { SetupDiEnumDeviceInfo(set.handle(), 0, &mut data) }.unwrap();
+
+        let props: PropertySelection = "desc".parse().unwrap();
+        let context = ReadContext::default();
+        let dev = WinDev::from_device_with(set.handle(), &data, &props, &context).unwrap();
+        assert_eq!(dev.guid, Guid::from(data.ClassGuid));
+        assert!(!dev.instance_id.is_empty());
+        assert!(dev.fname.is_none());
+        assert!(dev.hardware_ids.is_empty());
+        assert!(dev.interfaces.is_empty());
+        // looked up once, then reused for the rest of the class
+        let cached = context.class_names.borrow();
+        assert_eq!(cached.get(&data.ClassGuid.to_u128()), Some(&dev.class_name));
+    }
+}
@@ -0,0 +1,38 @@
+use crate::WinDev;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// What a snapshot remembers about a device: enough to match it up again in
+/// a later run and to name it in a diff.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    /// The instance ID, or the class GUID and name when it couldn't be read.
+    pub key: String,
+    pub name: Option<String>,
+    pub guid: String,
+}
+
+impl From<&WinDev> for SnapshotEntry {
+    fn from(dev: &WinDev) -> Self {
+        let name = dev.fname.clone().or_else(|| dev.desc.clone());
+        let guid = dev.guid.to_string();
+        let key = match dev.instance_id.is_empty() {
+            false => dev.instance_id.clone(),
+            true => format!("{} {}", guid, name.as_deref().unwrap_or("")),
+        };
+        Self { key, name, guid }
+    }
+}
+
+/// Splits two snapshots into the entries only in `new` (added) and the ones
+/// only in `old` (removed), each kept in its snapshot's order.
+pub fn diff<'a>(
+    old: &'a [SnapshotEntry],
+    new: &'a [SnapshotEntry],
+) -> (Vec<&'a SnapshotEntry>, Vec<&'a SnapshotEntry>) {
+    let old_keys: HashSet<&str> = old.iter().map(|e| e.key.as_str()).collect();
+    let new_keys: HashSet<&str> = new.iter().map(|e| e.key.as_str()).collect();
+    let added = new.iter().filter(|e| !old_keys.contains(e.key.as_str()));
+    let removed = old.iter().filter(|e| !new_keys.contains(e.key.as_str()));
+    (added.collect(), removed.collect())
+}